@@ -0,0 +1,97 @@
+//! Times the allocator's hot paths on an in-memory backing. Run with
+//! `cargo bench`; each case prints the mean time per operation.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rust_buddy::{pptr, BuddyAllocator};
+
+const SIZE: usize = 1 << 20;
+
+fn heap(min_order: usize) -> BuddyAllocator {
+    let mut heap = BuddyAllocator::new();
+    heap.init_with(vec![0u8; SIZE], min_order);
+    heap
+}
+
+/// Runs `f` until about a second has passed and returns the mean time of
+/// one of the `ops` operations it does per call.
+fn measure<F: FnMut()>(ops: usize, mut f: F) -> Duration {
+    let mut runs = 0;
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(1) {
+        f();
+        runs += 1;
+    }
+    start.elapsed() / (runs * ops) as u32
+}
+
+fn report(name: &str, time: Duration) {
+    println!("{:<24} {:>10.1?}/op", name, time);
+}
+
+/// Allocating and freeing one small block over and over, which only moves
+/// it between the same two lists once the first split is done.
+fn same_order() {
+    let mut heap = heap(0);
+    let time = measure(1000, || {
+        for _ in 0..1000 {
+            let off = heap.alloc(black_box(64)).unwrap();
+            heap.free_ptr(off).unwrap();
+        }
+    });
+    report("same order alloc/free", time);
+}
+
+/// Every allocation splits the whole memory down to order 0 and every free
+/// merges it all the way back up.
+fn split_all_the_way() {
+    let mut heap = heap(0);
+    let time = measure(1, || {
+        let off = heap.alloc(black_box(1)).unwrap();
+        heap.free_ptr(off).unwrap();
+    });
+    report("full split and merge", time);
+}
+
+/// Fills the memory with small blocks and frees every other one, so the
+/// list of that order holds thousands of blocks, then frees the rest: each
+/// of those merges has to find its buddy in that long list.
+fn long_free_list() {
+    let blocks = SIZE / 64;
+    let mut heap = heap(6);
+    let time = measure(blocks / 2, || {
+        let offs: Vec<pptr> = (0..blocks).map(|_| heap.alloc(64).unwrap()).collect();
+        for off in offs.iter().step_by(2) {
+            heap.free_ptr(*off).unwrap();
+        }
+        for off in offs.iter().skip(1).step_by(2) {
+            heap.free_ptr(*off).unwrap();
+        }
+    });
+    report("merge from a long list", time);
+}
+
+/// Allocates a burst of small blocks and frees them all, eagerly merging
+/// on every free or lazily leaving the blocks for the next burst to reuse.
+fn free_burst(lazy: bool) {
+    let blocks = 1024;
+    let mut heap = heap(0);
+    heap.set_lazy_coalesce(lazy);
+    let time = measure(blocks, || {
+        let offs: Vec<pptr> = (0..blocks).map(|_| heap.alloc(black_box(64)).unwrap()).collect();
+        for off in offs {
+            heap.free_ptr(off).unwrap();
+        }
+    });
+    let name = if lazy { "free burst, lazy" } else { "free burst, eager" };
+    report(name, time);
+}
+
+fn main() {
+    same_order();
+    split_all_the_way();
+    long_free_list();
+    free_burst(false);
+    free_burst(true);
+}
@@ -0,0 +1,63 @@
+//! Reads the input as a sequence of operations on a small heap and checks
+//! the allocator's invariants after each one. Run with `cargo fuzz run ops`.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_buddy::{pptr, BuddyAllocator};
+
+const SIZE: usize = 1 << 16;
+
+/// Lengths stay well below the size of the heap, so most inputs keep it
+/// partly full and exercise splits and merges at the small orders.
+fn length(hi: u8, lo: u8) -> usize {
+    1 + (usize::from(hi) << 8 | usize::from(lo)) % 4096
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut heap = BuddyAllocator::new();
+    heap.init_with(vec![0u8; SIZE], 0);
+    // Every live allocation with its length and the byte it was filled with.
+    let mut live: Vec<(pptr, usize, u8)> = vec![];
+    for (n, op) in data.chunks_exact(3).enumerate() {
+        let tag = n as u8;
+        match op[0] % 4 {
+            0 => {
+                let len = length(op[1], op[2]);
+                if let Ok(off) = heap.alloc(len) {
+                    heap.bytes_mut(off, len).fill(tag);
+                    live.push((off, len, tag));
+                }
+            }
+            1 if !live.is_empty() => {
+                let (off, len, tag) = live.swap_remove(usize::from(op[1]) % live.len());
+                assert!(heap.bytes(off, len).iter().all(|&b| b == tag), "Allocation was overwritten");
+                heap.free(off, len).unwrap();
+            }
+            2 if !live.is_empty() => {
+                let i = usize::from(op[1]) % live.len();
+                let (off, len, tag) = live[i];
+                let new_len = length(op[2], op[1]);
+                if let Ok(res) = heap.realloc(off, len, new_len) {
+                    let kept = usize::min(len, new_len);
+                    assert!(heap.bytes(res, kept).iter().all(|&b| b == tag), "Realloc lost data");
+                    heap.bytes_mut(res, new_len).fill(tag);
+                    live[i] = (res, new_len, tag);
+                }
+            }
+            3 if !live.is_empty() => {
+                let i = usize::from(op[1]) % live.len();
+                let (off, len, tag) = live[i];
+                let new_len = usize::min(len, length(op[2], op[1]));
+                heap.shrink_block(off, len, new_len).unwrap();
+                assert!(heap.bytes(off, new_len).iter().all(|&b| b == tag), "Shrinking lost data");
+                live[i] = (off, new_len, tag);
+            }
+            _ => {}
+        }
+        if let Err(e) = heap.validate() {
+            panic!("{}", e);
+        }
+        let used: usize = live.iter().map(|&(off, _, _)| heap.size_of(off)).sum();
+        assert_eq!(used, heap.used_bytes());
+    }
+});
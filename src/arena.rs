@@ -0,0 +1,219 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+use core::slice;
+
+use crate::{order_floor, pptr, Backing, BuddyAllocator, BuddyError};
+
+/// The backing that regions share, kept alive until the last of them is
+/// dropped.
+struct Owner(Box<dyn Backing>);
+
+// SAFETY: an `Owner` is never read or written once the regions over it are
+// made, so sharing it between threads shares nothing.
+unsafe impl Sync for Owner {}
+
+/// A range of bytes in a backing that is shared with other regions.
+struct Region {
+    _owner: Arc<Owner>,
+    ptr: NonNull<u8>,
+    len: usize
+}
+
+// SAFETY: no two regions over an owner overlap, so each region has unique
+// access to its bytes, like a `&mut [u8]`.
+unsafe impl Send for Region {}
+
+impl Backing for Region {}
+
+impl Deref for Region {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `ptr` points to `len` bytes of the owner that no other
+        // region covers, and the owner outlives `self`.
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for Region {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as in `deref`, and `&mut self` makes the access unique.
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+/// Several allocators over disjoint parts of one backing, behind a single
+/// offset space: an offset counts from the start of the backing, whichever
+/// arena it belongs to. `add_region` adds arenas over other backings to the
+/// same offset space.
+///
+/// `alloc` tries the arenas in order and takes the first one with room, so
+/// the later ones only fill up once the earlier ones are full. `free` hands
+/// the block back to the arena whose range holds it.
+pub struct ArenaSet {
+    /// Every arena with the offset of its first byte, in order of offset.
+    arenas: Vec<(pptr, BuddyAllocator)>
+}
+
+impl ArenaSet {
+    /// Splits `backing` into `count` arenas of the same size, the largest
+    /// power of two that fits `count` times, each with blocks no smaller
+    /// than `1 << min_order`. The bytes of the backing are left as they are.
+    pub fn new<B: Backing + 'static>(backing: B, count: usize, min_order: usize) -> Self {
+        assert!(count > 0, "No arenas");
+        let len = match backing.len() / count {
+            0 => 0,
+            part => 1 << order_floor(part)
+        };
+        let mut owner: Box<dyn Backing> = Box::new(backing);
+        let base = NonNull::new(owner.as_mut_ptr()).unwrap_or(NonNull::dangling());
+        let owner = Arc::new(Owner(owner));
+        let arenas = (0..count)
+            .map(|i| {
+                let start = i * len;
+                // SAFETY: `count * len` bytes fit in the backing.
+                let ptr = unsafe { NonNull::new_unchecked(base.as_ptr().add(start)) };
+                let region = Region { _owner: owner.clone(), ptr, len };
+                let mut heap = BuddyAllocator::new();
+                heap.init_with(region, min_order);
+                (start, heap)
+            })
+            .collect();
+        ArenaSet { arenas }
+    }
+    pub fn len(&self) -> usize {
+        self.arenas.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.arenas.is_empty()
+    }
+    pub fn arena(&self, i: usize) -> &BuddyAllocator {
+        &self.arenas[i].1
+    }
+    /// Gives access to one arena. Its offsets count from the start of the
+    /// arena, not of the backing.
+    pub fn arena_mut(&mut self, i: usize) -> &mut BuddyAllocator {
+        &mut self.arenas[i].1
+    }
+    /// Adds an arena over a backing of its own, such as a second file, whose
+    /// offsets start at `offset` in the shared offset space. It gets the
+    /// largest power of two that fits in `backing`, and is tried after every
+    /// arena at a lower offset. Blocks never merge across arenas, even ones
+    /// that sit next to each other. Fails with `InUse` at the first offset
+    /// another arena already covers, and adds nothing.
+    pub fn add_region<B: Backing + 'static>(&mut self, backing: B, offset: pptr, min_order: usize) -> Result<(), BuddyError> {
+        let mut heap = BuddyAllocator::new();
+        heap.init_with(backing, min_order);
+        let end = offset.checked_add(heap.capacity()).ok_or(BuddyError::SizeOverflow)?;
+        let i = self.arenas.partition_point(|&(start, _)| start < offset);
+        if let Some((start, prev)) = i.checked_sub(1).map(|j| &self.arenas[j]) {
+            if start + prev.capacity() > offset {
+                return Err(BuddyError::InUse { offset });
+            }
+        }
+        if let Some(&(start, _)) = self.arenas.get(i).filter(|&&(start, _)| start < end) {
+            return Err(BuddyError::InUse { offset: start });
+        }
+        self.arenas.insert(i, (offset, heap));
+        Ok(())
+    }
+    /// Index of the arena whose range holds `off`, if any does.
+    pub fn arena_of(&self, off: pptr) -> Option<usize> {
+        let i = self.arenas.partition_point(|&(start, _)| start <= off).checked_sub(1)?;
+        let (start, heap) = &self.arenas[i];
+        if heap.owns(off - start) {
+            Some(i)
+        } else {
+            None
+        }
+    }
+    /// Allocates from the first arena that has room. Fails with
+    /// `OutOfMemory` only if none of them does.
+    pub fn alloc(&mut self, len: usize) -> Result<pptr, BuddyError> {
+        for (start, heap) in self.arenas.iter_mut() {
+            match heap.alloc(len) {
+                Ok(off) => return Ok(*start + off),
+                Err(BuddyError::OutOfMemory) => continue,
+                Err(e) => return Err(Self::globalize(e, *start))
+            }
+        }
+        Err(BuddyError::OutOfMemory)
+    }
+    pub fn free(&mut self, off: pptr, len: usize) -> Result<(), BuddyError> {
+        let i = self.arena_of(off).ok_or(BuddyError::InvalidPointer { offset: off })?;
+        let (start, heap) = &mut self.arenas[i];
+        heap.free(off - *start, len).map_err(|e| Self::globalize(e, *start))
+    }
+    pub fn free_ptr(&mut self, off: pptr) -> Result<(), BuddyError> {
+        let i = self.arena_of(off).ok_or(BuddyError::InvalidPointer { offset: off })?;
+        let (start, heap) = &mut self.arenas[i];
+        heap.free_ptr(off - *start).map_err(|e| Self::globalize(e, *start))
+    }
+    /// Free bytes over all the arenas.
+    pub fn available(&self) -> usize {
+        self.arenas.iter().map(|(_, heap)| heap.available()).sum()
+    }
+    /// Makes the offset an arena reports in an error count from the start
+    /// of the backing.
+    fn globalize(e: BuddyError, start: pptr) -> BuddyError {
+        match e {
+            BuddyError::DoubleFree { offset } => BuddyError::DoubleFree { offset: start + offset },
+            BuddyError::InvalidPointer { offset } => BuddyError::InvalidPointer { offset: start + offset },
+            BuddyError::WriteAfterFree { offset } => BuddyError::WriteAfterFree { offset: start + offset },
+            BuddyError::InUse { offset } => BuddyError::InUse { offset: start + offset },
+            BuddyError::BufferOverflow { offset } => BuddyError::BufferOverflow { offset: start + offset },
+            BuddyError::SizeMismatch { offset } => BuddyError::SizeMismatch { offset: start + offset },
+            e => e
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frees_go_to_the_arena_that_holds_the_block() {
+        let mut set = ArenaSet::new(vec![0u8; 2048], 2, 0);
+        assert_eq!(set.len(), 2);
+        let a = set.alloc(1024).unwrap();
+        let b = set.alloc(512).unwrap();
+        assert_eq!((a, b), (0, 1024));
+        assert_eq!(set.arena_of(b), Some(1));
+        assert_eq!(set.arena(1).available(), 512);
+        set.free(b, 512).unwrap();
+        assert_eq!(set.arena(1).available(), 1024);
+        assert_eq!(set.arena(0).available(), 0);
+        set.free_ptr(a).unwrap();
+        assert_eq!(set.available(), 2048);
+        assert_eq!(set.free(a, 1024), Err(BuddyError::DoubleFree { offset: a }));
+        assert_eq!(set.free_ptr(4096), Err(BuddyError::InvalidPointer { offset: 4096 }));
+    }
+
+    #[test]
+    fn regions_serve_allocations_without_merging() {
+        let mut set = ArenaSet::new(vec![0u8; 1024], 1, 0);
+        set.add_region(vec![0u8; 1024], 1024, 0).unwrap();
+        set.add_region(vec![0u8; 512], 8192, 0).unwrap();
+        assert_eq!(set.add_region(vec![0u8; 1024], 1536, 0), Err(BuddyError::InUse { offset: 1536 }));
+        assert_eq!(set.add_region(vec![0u8; 8192], 4096, 0), Err(BuddyError::InUse { offset: 8192 }));
+        assert_eq!(set.len(), 3);
+        let a = set.alloc(1024).unwrap();
+        let b = set.alloc(512).unwrap();
+        let c = set.alloc(512).unwrap();
+        let d = set.alloc(512).unwrap();
+        assert_eq!((a, b, c, d), (0, 1024, 1536, 8192));
+        assert_eq!(set.alloc(1), Err(BuddyError::OutOfMemory));
+        set.free_ptr(c).unwrap();
+        assert_eq!(set.arena(1).available(), 512);
+        set.free_ptr(a).unwrap();
+        set.free_ptr(b).unwrap();
+        assert_eq!(set.arena(0).available(), 1024);
+        assert_eq!(set.arena(1).available(), 1024);
+        assert_eq!(set.alloc(2048), Err(BuddyError::OutOfMemory));
+        set.free_ptr(d).unwrap();
+        assert_eq!(set.available(), 2560);
+    }
+}
@@ -0,0 +1,48 @@
+use alloc::vec::Vec;
+use core::ops::DerefMut;
+
+use crate::pptr;
+
+/// Bytes an allocator hands out blocks from. Offsets are relative to the
+/// start, so the bytes may live anywhere: an owned buffer, a shared mapping
+/// or a region carved out of something else.
+///
+/// `alloc_obj` and `deref_obj` rely on the address of a block being aligned
+/// like its offset. That only holds when the start is aligned to the largest
+/// block in use, which a plain `Vec<u8>` does not promise; `deref_obj` panics
+/// rather than hand out a misaligned reference.
+pub trait Backing: DerefMut<Target = [u8]> + Send {
+    /// Address of the first byte.
+    fn base_ptr(&self) -> pptr {
+        self.as_ptr() as pptr
+    }
+    /// Number of bytes available, of which the allocator uses the largest
+    /// power of two that fits.
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Makes room for at least `len` bytes, keeping the current ones, and
+    /// tells whether it could. Backings of a fixed size leave this as is.
+    fn grow_to(&mut self, _len: usize) -> bool {
+        false
+    }
+}
+
+impl Backing for Vec<u8> {
+    fn grow_to(&mut self, len: usize) -> bool {
+        if len > Vec::len(self) {
+            if self.try_reserve(len - Vec::len(self)).is_err() {
+                return false;
+            }
+            self.resize(len, 0);
+        }
+        true
+    }
+}
+
+/// Memory the allocator does not own, such as a `static` buffer or one
+/// leaked from another allocator. It keeps its length.
+impl Backing for &'static mut [u8] {}
@@ -0,0 +1,99 @@
+use alloc::collections::BTreeMap;
+
+use crate::{pptr, BuddyAllocator, BuddyError, HeapSnapshot};
+
+/// Names a save point taken by `checkpoint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CheckpointId(usize);
+
+/// What `checkpoint` saves: a snapshot, and the lengths asked for by the
+/// live allocations, which a snapshot leaves out.
+pub(crate) struct SavePoint {
+    heap: HeapSnapshot,
+    requested: BTreeMap<pptr, usize>,
+    lengths: BTreeMap<pptr, usize>
+}
+
+impl BuddyAllocator {
+    /// Saves the free-lists, the allocation map and the accounting, so that
+    /// `rollback_to` can bring them back later. Save points nest: each one
+    /// is taken on top of the ones before it.
+    pub fn checkpoint(&mut self) -> CheckpointId {
+        self.checkpoints.push(SavePoint {
+            heap: self.snapshot(),
+            requested: self.requested.clone(),
+            lengths: self.lengths.clone()
+        });
+        CheckpointId(self.checkpoints.len() - 1)
+    }
+    /// Puts the bookkeeping back as it was at `id`, dropping every save
+    /// point taken after it; `id` itself stays, so it can be rolled back to
+    /// again. Like `tx_abort`, this does not restore the bytes in the
+    /// memory: allocations made since `id` are simply gone, and those freed
+    /// since are live again with whatever their bytes hold now, and with
+    /// the lengths they were allocated with.
+    ///
+    /// Fails with `InTransaction` if a transaction is in progress, and with
+    /// `InvalidCheckpoint` if `id` was dropped by an earlier rollback. Either
+    /// way nothing changes.
+    pub fn rollback_to(&mut self, id: CheckpointId) -> Result<(), BuddyError> {
+        if self.log.is_some() {
+            return Err(BuddyError::InTransaction);
+        }
+        let point = self.checkpoints.get(id.0).ok_or(BuddyError::InvalidCheckpoint)?;
+        let saved = Self::rebuild(&point.heap).ok_or(BuddyError::InvalidCheckpoint)?;
+        self.requested = point.requested.clone();
+        self.lengths = point.lengths.clone();
+        self.checkpoints.truncate(id.0 + 1);
+        self.buddies = saved.buddies;
+        self.free = saved.free;
+        self.allocated = saved.allocated;
+        self.tags = saved.tags;
+        self.available = saved.available;
+        self.size = saved.size;
+        self.last = saved.last;
+        for bin in self.fast_bins.iter_mut() {
+            bin.clear();
+        }
+        self.stats.live = self.allocated.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BuddyAllocator, BuddyError};
+
+    #[test]
+    fn rollback_revives_a_freed_block_with_its_length() {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        heap.set_redzone(true);
+        let a = heap.alloc(20).unwrap();
+        let id = heap.checkpoint();
+        heap.free_ptr(a).unwrap();
+        assert_eq!(heap.alloc(300), Ok(a));
+        heap.rollback_to(id).unwrap();
+        assert_eq!(heap.size_of(a), 32);
+        assert_eq!(heap.usable_size(a), 20);
+        assert_eq!(heap.validate(), Ok(()));
+        heap.bytes_mut(a, 21)[20] = 0;
+        assert_eq!(heap.free_ptr(a), Err(BuddyError::BufferOverflow { offset: a + 20 }));
+        assert_eq!(heap.available(), 1024);
+    }
+
+    #[test]
+    fn rollback_to_a_dropped_checkpoint_fails() {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        let first = heap.checkpoint();
+        heap.alloc(100).unwrap();
+        let second = heap.checkpoint();
+        heap.rollback_to(first).unwrap();
+        assert_eq!(heap.rollback_to(second), Err(BuddyError::InvalidCheckpoint));
+        heap.tx_begin();
+        assert_eq!(heap.rollback_to(first), Err(BuddyError::InTransaction));
+        heap.tx_end();
+        assert_eq!(heap.available(), 1024);
+    }
+}
@@ -0,0 +1,90 @@
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuddyError {
+    OutOfMemory,
+    ZeroSize,
+    SizeOverflow,
+    NotInitialized,
+    InvalidAlignment,
+    DoubleFree { offset: usize },
+    InvalidPointer { offset: usize },
+    WriteAfterFree { offset: usize },
+    InUse { offset: usize },
+    BufferOverflow { offset: usize },
+    SizeMismatch { offset: usize },
+    TagTooLong,
+    /// The call would change the heap in a way a transaction cannot undo,
+    /// such as resizing or resetting the memory.
+    InTransaction,
+    /// The save point was dropped by an earlier rollback, or no longer
+    /// restores to a valid heap.
+    InvalidCheckpoint
+}
+
+impl fmt::Display for BuddyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuddyError::OutOfMemory => write!(f, "Out of memory"),
+            BuddyError::ZeroSize => write!(f, "Cannot allocate zero bytes"),
+            BuddyError::SizeOverflow => write!(f, "Requested size is too large"),
+            BuddyError::NotInitialized => write!(f, "Memory is not initialized"),
+            BuddyError::InvalidAlignment => write!(f, "Alignment must be a power of two"),
+            BuddyError::DoubleFree { offset } => write!(f, "Block at {} is already free", offset),
+            BuddyError::InvalidPointer { offset } => write!(f, "No allocation starts at {}", offset),
+            BuddyError::WriteAfterFree { offset } => write!(f, "Free memory at {} was written to", offset),
+            BuddyError::InUse { offset } => write!(f, "Block at {} is still allocated", offset),
+            BuddyError::BufferOverflow { offset } => write!(f, "Write past the end of an allocation at {}", offset),
+            BuddyError::SizeMismatch { offset } => write!(f, "Freed length is larger than the allocation at {}", offset),
+            BuddyError::TagTooLong => write!(f, "Tag is longer than {} bytes", crate::MAX_TAG_LEN),
+            BuddyError::InTransaction => write!(f, "Cannot be done during a transaction"),
+            BuddyError::InvalidCheckpoint => write!(f, "Checkpoint cannot be rolled back to")
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuddyError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorruptionError {
+    OutOfBounds { offset: usize, order: usize },
+    Misaligned { offset: usize, order: usize },
+    Overlap { offset: usize, order: usize },
+    Cycle { offset: usize, order: usize },
+    WriteAfterFree { offset: usize, order: usize },
+    AvailableMismatch { recorded: usize, actual: usize },
+    Unaccounted { accounted: usize, size: usize },
+    /// A block has an order the allocator has no free-list for.
+    InvalidOrder { offset: usize, order: usize },
+    /// The highest order in use does not match the size of the memory.
+    InvalidLast { last: usize }
+}
+
+impl fmt::Display for CorruptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CorruptionError::OutOfBounds { offset, order } =>
+                write!(f, "Block at {} (order {}) lies outside the memory", offset, order),
+            CorruptionError::Misaligned { offset, order } =>
+                write!(f, "Block at {} is not aligned to its order {}", offset, order),
+            CorruptionError::Overlap { offset, order } =>
+                write!(f, "Block at {} (order {}) overlaps another block", offset, order),
+            CorruptionError::Cycle { offset, order } =>
+                write!(f, "Free-list {} loops back through block {}", order, offset),
+            CorruptionError::WriteAfterFree { offset, order } =>
+                write!(f, "Free block of order {} was written to at {}", order, offset),
+            CorruptionError::AvailableMismatch { recorded, actual } =>
+                write!(f, "Available is {} bytes but free-lists hold {} bytes", recorded, actual),
+            CorruptionError::Unaccounted { accounted, size } =>
+                write!(f, "Blocks account for {} of {} bytes", accounted, size),
+            CorruptionError::InvalidOrder { offset, order } =>
+                write!(f, "Block at {} has order {}, which has no free-list", offset, order),
+            CorruptionError::InvalidLast { last } =>
+                write!(f, "Last order {} does not fit the size of the memory", last)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CorruptionError {}
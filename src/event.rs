@@ -0,0 +1,33 @@
+use alloc::boxed::Box;
+
+use crate::{pptr, BuddyAllocator};
+
+/// A change to the blocks of an allocator, as reported to `on_event`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The free block of order `order` at `offset` was split in halves.
+    Split { order: usize, offset: pptr },
+    /// The free buddies of order `order` at `left` and `right` were merged
+    /// into one block of the next order.
+    Merge { order: usize, left: pptr, right: pptr },
+    Alloc { order: usize, offset: pptr },
+    Free { order: usize, offset: pptr }
+}
+
+impl BuddyAllocator {
+    /// Calls `f` with every split, merge, allocation and free from now on,
+    /// in the order they happen, in place of any earlier callback. Rolling
+    /// back a transaction is not reported.
+    pub fn on_event(&mut self, f: Box<dyn FnMut(Event) + Send>) {
+        self.observer = Some(f);
+    }
+    /// Stops reporting events.
+    pub fn clear_on_event(&mut self) {
+        self.observer = None;
+    }
+    pub(crate) fn emit(&mut self, event: Event) {
+        if let Some(f) = &mut self.observer {
+            f(event);
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use crate::{pptr, BuddyAllocator, BuddyError};
+
+impl BuddyAllocator {
+    /// Keeps up to `len` freed blocks of each order up to `max_order` aside
+    /// instead of merging them, and hands them out again first, most
+    /// recently freed first. Churn on small sizes then skips the merging and
+    /// splitting. Cached blocks count as available, but are not on the
+    /// free-lists until `flush_fast_bins`. A `len` of 0 turns the cache off
+    /// and flushes it.
+    ///
+    /// The cache is left alone inside a transaction: `tx_begin` flushes it,
+    /// and blocks freed before `tx_end` go straight to the free-lists.
+    pub fn set_fast_bins(&mut self, max_order: usize, len: usize) {
+        self.fast_bin_order = max_order;
+        self.fast_bin_len = len;
+        self.flush_fast_bins();
+    }
+    /// Moves every cached block onto the free-lists, merging it with its
+    /// buddy where it can.
+    pub fn flush_fast_bins(&mut self) {
+        for idx in 0..self.fast_bins.len() {
+            while let Some(off) = self.fast_bins[idx].pop() {
+                self.set_available(self.available - (1 << idx));
+                self.free_block(off, idx);
+            }
+        }
+    }
+    /// Caches the block of order `idx` at `off`, which is being freed, if
+    /// its bin has room, and tells whether it did.
+    pub(crate) fn bin(&mut self, off: pptr, idx: usize) -> bool {
+        let fits = self.log.is_none() && idx <= self.fast_bin_order && self.fast_bins[idx].len() < self.fast_bin_len;
+        if fits {
+            self.fast_bins[idx].push(off);
+            self.set_available(self.available + (1 << idx));
+        }
+        fits
+    }
+    /// Takes the most recently cached block of order `idx`, if there is one.
+    pub(crate) fn unbin(&mut self, idx: usize) -> Result<Option<pptr>, BuddyError> {
+        let off = match self.fast_bins[idx].last() {
+            Some(&off) if self.log.is_none() => off,
+            _ => return Ok(None)
+        };
+        if self.poison {
+            self.check_block_poison(off, idx)?;
+        }
+        self.fast_bins[idx].pop();
+        Ok(Some(off))
+    }
+    /// Tells whether `off` falls inside a cached block.
+    pub(crate) fn is_binned(&self, off: pptr) -> bool {
+        self.fast_bins.iter().enumerate().any(|(idx, bin)| {
+            bin.iter().any(|&b| b <= off && off < b + (1 << idx))
+        })
+    }
+    /// Every cached block as `(order, offset)`.
+    pub(crate) fn binned(&self) -> impl Iterator<Item = (usize, pptr)> + '_ {
+        self.fast_bins.iter().enumerate().flat_map(|(idx, bin)| bin.iter().map(move |&off| (idx, off)))
+    }
+}
@@ -0,0 +1,238 @@
+use std::cell::Cell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::ops::Deref;
+use std::path::Path;
+
+use crate::{pptr, BuddyAllocator};
+
+/// Granularity `sync_range` writes the memory back in.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Owns a `BuddyAllocator` together with the file its state is kept in.
+/// The image holds the allocator metadata followed by the memory, and is
+/// written back on `flush` and again when this is dropped.
+pub struct FileBackend {
+    file: File,
+    heap: BuddyAllocator,
+    /// Lengths of the metadata and of the memory in the image on disk, once
+    /// this has written or read one.
+    layout: Cell<Option<(usize, usize)>>
+}
+
+impl FileBackend {
+    /// Starts a fresh image of `size` bytes, discarding whatever was there.
+    pub fn create<P: AsRef<Path>>(path: P, size: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let mut heap = BuddyAllocator::new();
+        heap.init(size);
+        let backend = FileBackend { file, heap, layout: Cell::new(None) };
+        backend.flush()?;
+        Ok(backend)
+    }
+    /// Reopens the image at `path`, or starts a fresh one of `size` bytes
+    /// if the file is missing or empty. Fails with `InvalidData`, leaving
+    /// the file as it is, if it holds anything but a valid allocator.
+    pub fn open<P: AsRef<Path>>(path: P, size: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path.as_ref())?;
+        if file.metadata()?.len() == 0 {
+            drop(file);
+            return Self::create(path, size);
+        }
+        let mut r = BufReader::new(&file);
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Image is not valid");
+        let heap = BuddyAllocator::load_image(&mut r).ok_or_else(invalid)?;
+        let meta = r.stream_position()? as usize - heap.memory.len();
+        drop(r);
+        let layout = Cell::new(Some((meta, heap.memory.len())));
+        Ok(FileBackend { file, heap, layout })
+    }
+    pub fn heap(&self) -> &BuddyAllocator {
+        &self.heap
+    }
+    pub fn heap_mut(&mut self) -> &mut BuddyAllocator {
+        &mut self.heap
+    }
+    pub fn flush(&self) -> io::Result<()> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(0))?;
+        let mut w = BufWriter::new(file);
+        self.heap.save_image(&mut w)?;
+        w.flush()?;
+        drop(w);
+        let len = file.stream_position()?;
+        file.set_len(len)?;
+        file.sync_data()?;
+        let meta = len as usize - self.heap.memory.len();
+        self.layout.set(Some((meta, self.heap.memory.len())));
+        Ok(())
+    }
+    /// Like `flush`, but of the memory only writes back the pages that
+    /// overlap `len` bytes at `off`, rounded out to `PAGE_SIZE`. The
+    /// metadata is always written whole. Falls back to `flush` when the
+    /// metadata or the memory changed length since the image was last
+    /// written, as the bytes after them would have to move.
+    pub fn sync_range(&self, off: pptr, len: usize) -> io::Result<()> {
+        let mut meta = vec![];
+        self.heap.save_metadata(&mut meta)?;
+        if self.layout.get() != Some((meta.len(), self.heap.memory.len())) {
+            return self.flush();
+        }
+        let (start, end) = page_span(off, len, self.heap.memory.len());
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&meta)?;
+        if start < end {
+            file.seek(SeekFrom::Start((meta.len() + start) as u64))?;
+            file.write_all(&self.heap.memory[start..end])?;
+        }
+        file.sync_data()
+    }
+}
+
+/// The pages that overlap `len` bytes at `off`, as a range of bytes cut
+/// short at `limit`.
+fn page_span(off: pptr, len: usize, limit: usize) -> (usize, usize) {
+    let start = off / PAGE_SIZE * PAGE_SIZE;
+    let end = usize::min(off.saturating_add(len).div_ceil(PAGE_SIZE).saturating_mul(PAGE_SIZE), limit);
+    (start, end)
+}
+
+impl Drop for FileBackend {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// An image loaded from a file opened for reading only, for tools that
+/// inspect a heap without changing it. It derefs to the allocator, so
+/// `validate`, `print`, `snapshot` and the other `&self` methods work, but
+/// `alloc`, `free` and the rest that take `&mut self` do not compile, and
+/// nothing is ever written back.
+pub struct ReadOnlyImage {
+    heap: BuddyAllocator
+}
+
+impl ReadOnlyImage {
+    /// Loads the image at `path`. Fails with `InvalidData` if the file does
+    /// not hold a valid allocator, as written by `FileBackend`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut r = BufReader::new(File::open(path)?);
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Image is not valid");
+        let heap = BuddyAllocator::load_image(&mut r).ok_or_else(invalid)?;
+        Ok(ReadOnlyImage { heap })
+    }
+}
+
+impl Deref for ReadOnlyImage {
+    type Target = BuddyAllocator;
+    fn deref(&self) -> &BuddyAllocator {
+        &self.heap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// A path in the temporary directory that no other test uses.
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("rust-buddy-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn reopen_keeps_allocations() {
+        let path = temp_path("file-reopen");
+        let live = {
+            let mut backend = FileBackend::open(&path, 4096).unwrap();
+            backend.heap_mut().alloc_tagged(100, "a").unwrap();
+            backend.heap_mut().alloc(300).unwrap();
+            backend.flush().unwrap();
+            backend.heap().report_leaks()
+        };
+        let backend = FileBackend::open(&path, 4096).unwrap();
+        assert_eq!(backend.heap().report_leaks(), live);
+        assert_eq!(backend.heap().tag_of(live[0].0), Some("a"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_image_is_not_overwritten() {
+        let path = temp_path("file-corrupt");
+        fs::write(&path, b"not an image").unwrap();
+        let err = FileBackend::open(&path, 4096).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read(&path).unwrap(), b"not an image");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn page_span_covers_the_range() {
+        assert_eq!(page_span(0, 1, 65536), (0, PAGE_SIZE));
+        assert_eq!(page_span(5000, 10, 65536), (PAGE_SIZE, 2 * PAGE_SIZE));
+        assert_eq!(page_span(4000, 200, 65536), (0, 2 * PAGE_SIZE));
+        assert_eq!(page_span(PAGE_SIZE, PAGE_SIZE, 65536), (PAGE_SIZE, 2 * PAGE_SIZE));
+        assert_eq!(page_span(9000, 100, 10000), (2 * PAGE_SIZE, 10000));
+        assert_eq!(page_span(usize::MAX - 10, 100, 65536), (usize::MAX / PAGE_SIZE * PAGE_SIZE, 65536));
+    }
+
+    #[test]
+    fn sync_range_writes_only_its_pages() {
+        let path = temp_path("file-sync-range");
+        let mut backend = FileBackend::open(&path, 16384).unwrap();
+        let a = backend.heap_mut().alloc(8192).unwrap();
+        backend.flush().unwrap();
+        let (meta, _) = backend.layout.get().unwrap();
+        backend.heap_mut().bytes_mut(a, 8192).fill(7);
+        backend.sync_range(a + 5000, 10).unwrap();
+        let image = fs::read(&path).unwrap();
+        assert!(image[meta + a + PAGE_SIZE..meta + a + 2 * PAGE_SIZE].iter().all(|&b| b == 7));
+        assert!(image[meta + a..meta + a + PAGE_SIZE].iter().all(|&b| b != 7));
+        drop(backend);
+        assert!(fs::read(&path).unwrap()[meta + a..meta + a + 8192].iter().all(|&b| b == 7));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_only_image_leaves_the_file_alone() {
+        let path = temp_path("file-read-only");
+        let mut heap = BuddyAllocator::new();
+        heap.init(4096);
+        let a = heap.alloc(100).unwrap();
+        heap.bytes_mut(a, 5).copy_from_slice(b"hello");
+        let mut image = vec![];
+        heap.save_image(&mut image).unwrap();
+        fs::write(&path, &image).unwrap();
+        let mut perms = fs::metadata(&path).unwrap().permissions();
+        perms.set_readonly(true);
+        fs::set_permissions(&path, perms).unwrap();
+        let mtime = fs::metadata(&path).unwrap().modified().unwrap();
+
+        let ro = ReadOnlyImage::open(&path).unwrap();
+        assert_eq!(ro.validate(), Ok(()));
+        assert_eq!(ro.bytes(a, 5), b"hello");
+        assert_eq!(ro.snapshot(), heap.snapshot());
+        let mut out = vec![];
+        ro.write_state(&mut out).unwrap();
+        assert!(!out.is_empty());
+        drop(ro);
+
+        assert_eq!(fs::read(&path).unwrap(), image);
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), mtime);
+        fs::remove_file(&path).unwrap();
+    }
+}
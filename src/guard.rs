@@ -0,0 +1,75 @@
+use core::ops::{Deref, DerefMut};
+
+use crate::{pptr, BuddyAllocator, BuddyError};
+
+/// An allocation that is freed when it goes out of scope. It borrows the
+/// allocator for as long as it lives, so nothing else can free or reuse the
+/// block in the meantime.
+pub struct Allocation<'a> {
+    heap: &'a mut BuddyAllocator,
+    off: pptr,
+    len: usize
+}
+
+impl<'a> Allocation<'a> {
+    pub fn offset(&self) -> pptr {
+        self.off
+    }
+    /// The `len` bytes that were asked for.
+    pub fn as_slice(&self) -> &[u8] {
+        self.heap.bytes(self.off, self.len)
+    }
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        self.heap.bytes_mut(self.off, self.len)
+    }
+}
+
+impl Deref for Allocation<'_> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl DerefMut for Allocation<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.as_mut_slice()
+    }
+}
+
+impl Drop for Allocation<'_> {
+    fn drop(&mut self) {
+        // The block can only fail to free if its red zone was overwritten,
+        // and it is freed all the same then.
+        let _ = self.heap.free_ptr(self.off);
+    }
+}
+
+impl BuddyAllocator {
+    /// Like `alloc`, but the block is freed when the returned guard is
+    /// dropped.
+    pub fn alloc_guarded(&mut self, len: usize) -> Result<Allocation<'_>, BuddyError> {
+        let off = self.alloc(len)?;
+        Ok(Allocation { heap: self, off, len })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::BuddyAllocator;
+
+    #[test]
+    fn dropping_a_guard_frees_the_block() {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        let off = {
+            let mut a = heap.alloc_guarded(100).unwrap();
+            assert_eq!(a.len(), 100);
+            a.as_mut_slice().fill(7);
+            assert!(a.iter().all(|&b| b == 7));
+            a.offset()
+        };
+        assert_eq!(heap.size_of(off), 0);
+        assert_eq!(heap.available(), 1024);
+    }
+}
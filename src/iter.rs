@@ -0,0 +1,54 @@
+use alloc::rc::Rc;
+use core::cell::RefCell;
+
+use crate::{pptr, Buddy, BuddyAllocator};
+
+/// Walks every free-list in increasing order, yielding
+/// `(order, offset, size)` for each free block.
+///
+/// A list that holds more blocks than the memory could fit must loop back on
+/// itself. The walk gives up on such a list and moves on to the next one, so
+/// it always terminates.
+pub struct FreeBlocks<'a> {
+    heap: &'a BuddyAllocator,
+    idx: usize,
+    count: usize,
+    curr: Option<Rc<RefCell<Buddy>>>,
+    pub(crate) overrun: Option<(usize, pptr)>
+}
+
+impl<'a> FreeBlocks<'a> {
+    pub(crate) fn new(heap: &'a BuddyAllocator) -> Self {
+        FreeBlocks {
+            heap,
+            idx: 0,
+            count: 0,
+            curr: heap.buddies[0].clone(),
+            overrun: None
+        }
+    }
+}
+
+impl<'a> Iterator for FreeBlocks<'a> {
+    type Item = (usize, pptr, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(b) = self.curr.take() {
+                let b = b.borrow();
+                self.count += 1;
+                if self.count <= self.heap.size >> self.idx {
+                    self.curr = b.next.clone();
+                    return Some((self.idx, b.off, 1 << self.idx));
+                }
+                self.overrun.get_or_insert((self.idx, b.off));
+            }
+            self.idx += 1;
+            if self.idx >= self.heap.buddies.len() {
+                return None;
+            }
+            self.count = 0;
+            self.curr = self.heap.buddies[self.idx].clone();
+        }
+    }
+}
@@ -0,0 +1,2317 @@
+#![allow(dead_code)]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg_attr(not(feature = "std"), macro_use)]
+extern crate alloc;
+
+mod arena;
+mod backing;
+mod checkpoint;
+mod error;
+mod event;
+mod fastbin;
+#[cfg(feature = "std")]
+mod file;
+mod guard;
+mod iter;
+mod memory;
+mod neighbor;
+#[cfg(feature = "std")]
+mod persist;
+mod poison;
+mod policy;
+mod redzone;
+mod ptr;
+mod snapshot;
+mod stats;
+#[cfg(feature = "std")]
+mod sync;
+mod tag;
+mod tree;
+#[cfg(feature = "std")]
+mod wal;
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::rc::{Rc,Weak};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+
+use memory::Memory;
+
+pub use arena::ArenaSet;
+pub use backing::Backing;
+pub use checkpoint::CheckpointId;
+pub use error::{BuddyError, CorruptionError};
+pub use event::Event;
+#[cfg(feature = "std")]
+pub use file::{FileBackend, ReadOnlyImage, PAGE_SIZE};
+pub use guard::Allocation;
+pub use iter::FreeBlocks;
+pub use memory::MAX_ALIGN;
+pub use neighbor::Neighbor;
+pub use poison::POISON;
+pub use policy::Policy;
+pub use redzone::{REDZONE, REDZONE_LEN};
+pub use ptr::{Plain, Ptr};
+pub use snapshot::HeapSnapshot;
+pub use stats::{OrderStats, Stats, WasteReport};
+#[cfg(feature = "std")]
+pub use sync::{SyncAllocation, SyncBuddy};
+pub use tag::MAX_TAG_LEN;
+pub use tree::TreeFormat;
+#[cfg(feature = "std")]
+pub use wal::WalBackend;
+
+#[allow(non_camel_case_types)]
+pub type pptr = usize;
+
+/// A free block on one of the free-lists, which are doubly linked so that
+/// any block can be taken out without walking its list.
+#[derive(Clone)]
+pub(crate) struct Buddy {
+    off: pptr,
+    next: Option<Rc<RefCell<Buddy>>>,
+    prev: Weak<RefCell<Buddy>>
+}
+
+/// One change made inside a transaction, holding the value it replaced.
+enum Undo {
+    Head(usize, Option<Rc<RefCell<Buddy>>>),
+    Next(Rc<RefCell<Buddy>>, Option<Rc<RefCell<Buddy>>>),
+    Prev(Rc<RefCell<Buddy>>, Weak<RefCell<Buddy>>),
+    Free(pptr, Option<(usize, Rc<RefCell<Buddy>>)>),
+    Available(usize),
+    Allocated(pptr, Option<usize>),
+    Length(pptr, Option<usize>),
+    Requested(pptr, Option<usize>),
+    Tag(pptr, Option<String>),
+    /// The counters as they were at `tx_begin`.
+    Stats(Stats)
+}
+
+pub struct BuddyAllocator {
+    buddies: Vec<Option<Rc<RefCell<Buddy>>>>,
+    available: usize,
+    size: usize,
+    last: usize,
+    min_order: usize,
+    /// Freed blocks of this order or above are not merged with their buddy.
+    max_coalesce_order: usize,
+    /// Free-lists are kept sorted by offset rather than most recent first.
+    address_ordered: bool,
+    /// Freed blocks are left unmerged until an allocation runs out.
+    lazy_coalesce: bool,
+    /// Most orders one allocation may split down from.
+    max_split_distance: usize,
+    /// Free bytes only `alloc_reserved` may take.
+    reserved: usize,
+    allocated: BTreeMap<pptr, usize>,
+    /// Every block on the free-lists by offset, with its order, so a buddy
+    /// is found without walking a list.
+    free: BTreeMap<pptr, (usize, Rc<RefCell<Buddy>>)>,
+    memory: Box<dyn Backing>,
+    log: Option<Vec<Undo>>,
+    stats: Stats,
+    policy: Policy,
+    poison: bool,
+    redzone: bool,
+    /// Requested length of every live allocation that has a red zone.
+    requested: BTreeMap<pptr, usize>,
+    /// Length asked for by every live allocation, red zone or not.
+    lengths: BTreeMap<pptr, usize>,
+    observer: Option<Box<dyn FnMut(Event) + Send>>,
+    /// Save points taken by `checkpoint`, oldest first.
+    checkpoints: Vec<checkpoint::SavePoint>,
+    /// Tag of every live allocation made with `alloc_tagged`.
+    tags: BTreeMap<pptr, String>,
+    /// Recently freed blocks of each order kept aside for reuse, most recent
+    /// last. See `set_fast_bins`.
+    fast_bins: Vec<Vec<pptr>>,
+    fast_bin_order: usize,
+    fast_bin_len: usize
+}
+
+const fn num_bits<T>() -> u32 { (core::mem::size_of::<T>() << 3) as u32 }
+
+/// Smallest order whose block holds `x` bytes: `1 << order >= x`.
+/// Both 0 and 1 map to order 0.
+#[inline]
+fn order_ceil(x: usize) -> usize {
+    if x <= 1 {
+        0
+    } else {
+        (num_bits::<usize>() - (x-1).leading_zeros()) as usize
+    }
+}
+
+/// Largest order whose block fits in `x` bytes: `1 << order <= x`.
+/// There is none for 0, which maps to order 0 like 1 does.
+#[inline]
+fn order_floor(x: usize) -> usize {
+    if x == 0 {
+        0
+    } else {
+        (num_bits::<usize>() - 1 - x.leading_zeros()) as usize
+    }
+}
+
+/// Puts back the entry of `map` at `off` as it was before a change: `old`,
+/// or no entry at all.
+fn restore<V>(map: &mut BTreeMap<pptr, V>, off: pptr, old: Option<V>) {
+    match old {
+        Some(v) => {
+            map.insert(off, v);
+        }
+        None => {
+            map.remove(&off);
+        }
+    }
+}
+
+impl fmt::Debug for BuddyAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut free_blocks = BTreeMap::new();
+        for (idx, _, _) in self.free_blocks() {
+            *free_blocks.entry(idx).or_insert(0) += 1;
+        }
+        f.debug_struct("BuddyAllocator")
+            .field("size", &self.size)
+            .field("available", &self.available)
+            .field("last", &self.last)
+            .field("free_blocks", &free_blocks)
+            .finish()
+    }
+}
+
+/// A one-line summary of the memory, for logs. `Debug` and `print` give
+/// more detail.
+impl fmt::Display for BuddyAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Buddy[size={} used={} free={} largest={} frag={:.2}]",
+            self.capacity(),
+            self.used_bytes(),
+            self.free_bytes(),
+            self.largest_free_block(),
+            self.fragmentation()
+        )
+    }
+}
+
+impl Default for BuddyAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BuddyAllocator {
+    pub fn new() -> Self {
+        Self::with_orders(31)
+    }
+    /// Creates an allocator with free-lists for orders `0..=max_order`,
+    /// so it can manage at most `1 << max_order` bytes.
+    pub fn with_orders(max_order: usize) -> Self {
+        assert!(max_order < num_bits::<usize>() as usize, "Too many orders");
+        BuddyAllocator {
+            buddies: vec![None; max_order + 1],
+            available: 0,
+            size: 0,
+            last: 0,
+            min_order: 0,
+            max_coalesce_order: max_order + 1,
+            address_ordered: false,
+            lazy_coalesce: false,
+            max_split_distance: usize::MAX,
+            reserved: 0,
+            allocated: BTreeMap::new(),
+            free: BTreeMap::new(),
+            memory: Box::new(Memory::new(0)),
+            log: None,
+            stats: Stats::default(),
+            policy: Policy::default(),
+            poison: false,
+            redzone: false,
+            requested: BTreeMap::new(),
+            lengths: BTreeMap::new(),
+            observer: None,
+            checkpoints: vec![],
+            tags: BTreeMap::new(),
+            fast_bins: vec![vec![]; max_order + 1],
+            fast_bin_order: 0,
+            fast_bin_len: 0
+        }
+    }
+    /// Creates an allocator that hands out blocks from `buf`, which it
+    /// borrows for good rather than owns: a `static` buffer, or one from
+    /// `Box::leak`. Like `init_with`, only the largest power of two that
+    /// fits in `buf` is used.
+    pub fn from_slice(buf: &'static mut [u8]) -> Self {
+        let mut heap = Self::new();
+        heap.init_with(buf, 0);
+        heap
+    }
+    /// Tells whether the allocator has memory to hand out, which it does
+    /// not until `init` or `init_with` gives it some.
+    pub fn is_initialized(&self) -> bool {
+        self.size != 0
+    }
+    pub fn init(&mut self, size: usize) {
+        self.init_with_min_order(size, 0);
+    }
+    /// Like `init`, but fills the whole memory with `fill`, so bytes of an
+    /// allocation that were never written stand out in a dump. Poisoning,
+    /// when on, fills free memory with `POISON` instead.
+    pub fn init_with_fill(&mut self, size: usize, fill: u8) {
+        self.init(size);
+        if !self.poison {
+            self.memory[..self.size].fill(fill);
+        }
+    }
+    /// Like `init`, but rounds `usable` up rather than down, so that at
+    /// least that many bytes are available afterwards. Fails with
+    /// `SizeOverflow` if that takes more than the largest order.
+    pub fn init_for_usable(&mut self, usable: usize) -> Result<(), BuddyError> {
+        if usable == 0 {
+            return Err(BuddyError::ZeroSize);
+        }
+        let order = order_ceil(usable);
+        if order >= self.buddies.len() {
+            return Err(BuddyError::SizeOverflow);
+        }
+        self.init(1 << order);
+        Ok(())
+    }
+    /// Like `init`, but blocks are never split below `min_order`, so every
+    /// allocation takes at least `1 << min_order` bytes. A `size` of 0
+    /// leaves the allocator without memory, as if `init` was never called.
+    pub fn init_with_min_order(&mut self, size: usize, min_order: usize) {
+        let size = match size {
+            0 => 0,
+            size => 1 << usize::min(order_floor(size), self.buddies.len() - 1)
+        };
+        self.init_with(Memory::new(size), min_order);
+    }
+    /// Like `init_with_min_order`, but hands out blocks from `backing`
+    /// instead of a buffer of its own. Only the largest power of two that
+    /// fits in `backing` is used, and its bytes are left as they are.
+    ///
+    /// An open transaction is dropped along with everything else. Panics if
+    /// `backing` is not empty but too small for a single block of order
+    /// `min_order`.
+    pub fn init_with<B: Backing + 'static>(&mut self, backing: B, min_order: usize) {
+        let max_order = self.buddies.len() - 1;
+        assert!(min_order <= max_order, "Minimum order is above the largest order");
+        let size = if backing.is_empty() {
+            0
+        } else {
+            1 << usize::min(order_floor(backing.len()), max_order)
+        };
+        assert!(size == 0 || size >> min_order > 0, "Memory is smaller than a block of the minimum order");
+        self.min_order = min_order;
+        self.size = size;
+        self.memory = Box::new(backing);
+        self.stats = Stats::default();
+        self.log = None;
+        self.lay_out();
+    }
+    /// Drops every allocation at once and goes back to the free blocks of a
+    /// fresh memory: a single one right after `init`, or a few aligned ones
+    /// once `grow` has made the size other than a power of two. The bytes in
+    /// the memory are left as they are. Fails with `InTransaction` while a
+    /// transaction is in progress, as it could not be undone.
+    pub fn reset(&mut self) -> Result<(), BuddyError> {
+        if self.log.is_some() {
+            return Err(BuddyError::InTransaction);
+        }
+        self.lay_out();
+        Ok(())
+    }
+    /// Does the work of `reset`, without the check.
+    fn lay_out(&mut self) {
+        for b in self.buddies.iter_mut() {
+            *b = None;
+        }
+        self.allocated.clear();
+        self.free.clear();
+        self.requested.clear();
+        self.lengths.clear();
+        self.tags.clear();
+        for bin in self.fast_bins.iter_mut() {
+            bin.clear();
+        }
+        self.stats.live = 0;
+        self.available = self.size;
+        if self.poison {
+            self.memory[..self.size].fill(POISON);
+        }
+        if self.size == 0 {
+            self.last = 0;
+            return;
+        }
+        self.last = self.top_order() + 1;
+        for (idx, off) in self.aligned_blocks(0, self.size) {
+            self.push(idx, off);
+        }
+    }
+    /// Extends the memory by `additional` bytes, rounded down to whole
+    /// blocks of the minimum order. The bytes already in the memory are kept
+    /// at the same offsets, and the new space is freed so it merges with any
+    /// free block at the old end.
+    ///
+    /// The backing may move its bytes to make room, so any slice into the
+    /// memory must be dropped first. Offsets handed out earlier stay valid.
+    /// Fails with `OutOfMemory` if the backing cannot grow that far, and
+    /// with `InTransaction` while a transaction is in progress.
+    pub fn grow(&mut self, additional: usize) -> Result<(), BuddyError> {
+        if !self.is_initialized() {
+            return Err(BuddyError::NotInitialized);
+        }
+        if self.log.is_some() {
+            return Err(BuddyError::InTransaction);
+        }
+        let unit = 1 << self.min_order;
+        let size = self.size.checked_add(additional).ok_or(BuddyError::SizeOverflow)? / unit * unit;
+        if size <= self.size {
+            return Ok(());
+        }
+        if self.memory.len() < size && !self.memory.grow_to(size) {
+            return Err(BuddyError::OutOfMemory);
+        }
+        if self.poison {
+            self.memory[self.size..size].fill(POISON);
+        }
+        let old = core::mem::replace(&mut self.size, size);
+        self.last = self.top_order() + 1;
+        for (idx, off) in self.aligned_blocks(old, size) {
+            self.free_block(off, idx);
+        }
+        Ok(())
+    }
+    /// Gives up the memory past `new_size`, rounded up to whole blocks of
+    /// the minimum order. That tail must be free: if an allocation reaches
+    /// into it, this fails with `InUse` and nothing changes. A free block
+    /// that straddles the new end is split so that its lower part stays.
+    ///
+    /// The backing keeps its length; the bytes past the new size are just
+    /// no longer handed out. Fails with `InTransaction` while a transaction
+    /// is in progress.
+    pub fn shrink_to(&mut self, new_size: usize) -> Result<(), BuddyError> {
+        if self.log.is_some() {
+            return Err(BuddyError::InTransaction);
+        }
+        let unit = 1 << self.min_order;
+        let size = new_size.div_ceil(unit) * unit;
+        if size >= self.size {
+            return Ok(());
+        }
+        if let Some((&off, &idx)) = self.allocated.iter().next_back() {
+            if off + (1 << idx) > size {
+                return Err(BuddyError::InUse { offset: off });
+            }
+        }
+        self.flush_fast_bins();
+        let tail: Vec<(usize, pptr)> = self.free_blocks()
+            .filter(|&(_, off, len)| off + len > size)
+            .map(|(idx, off, _)| (idx, off))
+            .collect();
+        for (idx, off) in tail {
+            self.unlink(idx, off);
+            self.set_available(self.available - (1 << idx));
+            if off < size {
+                for (idx, off) in self.aligned_blocks(off, size) {
+                    self.set_available(self.available + (1 << idx));
+                    self.push(idx, off);
+                }
+            }
+        }
+        self.size = size;
+        self.last = if size == 0 { 0 } else { self.top_order() + 1 };
+        Ok(())
+    }
+    /// Takes the block at `off` out of the free-list of order `idx`, if it
+    /// is there.
+    fn unlink(&mut self, idx: usize, off: pptr) {
+        let node = match self.free.get(&off) {
+            Some((i, node)) if *i == idx => node.clone(),
+            _ => return
+        };
+        let (prev, next) = {
+            let b = node.borrow();
+            (b.prev.clone(), b.next.clone())
+        };
+        if let Some(next) = &next {
+            self.set_prev(next, prev.clone());
+        }
+        match prev.upgrade() {
+            Some(p) => self.set_next(&p, next),
+            None => self.set_head(idx, next)
+        }
+        self.set_free(off, None);
+    }
+    /// Largest order a free block can reach in a memory of this size.
+    fn top_order(&self) -> usize {
+        usize::min(order_floor(self.size), self.buddies.len() - 1)
+    }
+    /// Splits `[start, end)` into the fewest blocks that are each aligned
+    /// to their own size, as `(order, offset)` pairs.
+    fn aligned_blocks(&self, start: pptr, end: pptr) -> Vec<(usize, pptr)> {
+        let mut blocks = vec![];
+        let mut off = start;
+        while off < end {
+            let mut idx = usize::min(order_floor(end - off), self.buddies.len() - 1);
+            if off != 0 {
+                idx = usize::min(idx, off.trailing_zeros() as usize);
+            }
+            blocks.push((idx, off));
+            off += 1 << idx;
+        }
+        blocks
+    }
+    fn push(&mut self, idx: usize, off: pptr) {
+        if self.address_ordered {
+            let prev = self.free.range(..off).rev().find(|(_, (i, _))| *i == idx).map(|(_, (_, node))| node.clone());
+            if let Some(prev) = prev {
+                let next = prev.borrow().next.clone();
+                let n = Rc::new(RefCell::new(Buddy {
+                    off,
+                    next: next.clone(),
+                    prev: Rc::downgrade(&prev)
+                }));
+                if let Some(next) = &next {
+                    self.set_prev(next, Rc::downgrade(&n));
+                }
+                self.set_next(&prev, Some(n.clone()));
+                self.set_free(off, Some((idx, n)));
+                return;
+            }
+        }
+        let head = self.buddies[idx].clone();
+        let n = Rc::new(RefCell::new(Buddy {
+            off,
+            next: head.clone(),
+            prev: Weak::new()
+        }));
+        if let Some(head) = &head {
+            self.set_prev(head, Rc::downgrade(&n));
+        }
+        self.set_head(idx, Some(n.clone()));
+        self.set_free(off, Some((idx, n)));
+    }
+    /// End of the orders a block of order `idx` may be split from.
+    fn split_limit(&self, idx: usize) -> usize {
+        let end = idx.saturating_add(self.max_split_distance).saturating_add(1);
+        usize::min(end, self.buddies.len())
+    }
+    /// Takes a free block of order `idx`. If that list is empty, a larger
+    /// block picked by the policy is split in halves down to `idx`, and the
+    /// upper half left over at each level goes onto its free-list. Finds
+    /// nothing for an order past the largest one rather than panic, though
+    /// `order_for` already turns those into `SizeOverflow`.
+    fn find_free_memory(&mut self, idx: usize) -> Option<pptr> {
+        let (from, res) = self.pick_free(idx)?;
+        self.split_down(res, from, idx);
+        Some(res)
+    }
+    /// The free block `find_free_memory` would split for order `idx`, as
+    /// `(order, offset)`, without taking it.
+    fn pick_free(&self, idx: usize) -> Option<(usize, pptr)> {
+        if idx >= self.buddies.len() {
+            return None;
+        }
+        let limit = self.split_limit(idx);
+        let mut orders = (idx..limit).filter(|&i| self.buddies[i].is_some());
+        // The free blocks are indexed by offset, so the first match is the
+        // lowest one.
+        let lowest = |fits: &dyn Fn(usize) -> bool| {
+            self.free.iter().find(|(_, &(i, _))| fits(i)).map(|(&off, &(i, _))| (i, off))
+        };
+        let head = |i: usize| self.buddies[i].as_ref().map(|b| (i, b.borrow().off));
+        match self.policy {
+            Policy::SmallestFit => head(orders.next()?),
+            Policy::TopDown if self.buddies[idx].is_some() => head(idx),
+            Policy::TopDown => head(orders.next_back()?),
+            Policy::FirstFit => lowest(&|i| idx <= i && i < limit),
+            Policy::BestFit => {
+                let from = orders.next()?;
+                lowest(&|i| i == from)
+            }
+        }
+    }
+    /// Takes the free block of order `from` at `res` off its list and splits
+    /// it down to order `idx`, putting the upper halves back on the lists.
+    fn split_down(&mut self, res: pptr, from: usize, idx: usize) {
+        self.unlink(from, res);
+        for k in (idx..from).rev() {
+            self.emit(Event::Split { order: k + 1, offset: res });
+            self.push(k, res + (1 << k));
+        }
+        self.stats.splits += from - idx;
+    }
+    /// Allocates a block of at least `len` bytes. On failure nothing is
+    /// changed: no block is split and an open transaction stays as it was.
+    pub fn alloc(&mut self, len: usize) -> Result<pptr, BuddyError> {
+        self.alloc_aligned(len, 1)
+    }
+    /// Like `alloc`, but also returns how many bytes the block really holds:
+    /// `len` rounded up to its order, or just `len` with red zones on. The
+    /// caller may use all of them.
+    pub fn alloc_with_order(&mut self, len: usize) -> Result<(pptr, usize), BuddyError> {
+        let off = self.alloc(len)?;
+        Ok((off, self.usable_size(off)))
+    }
+    /// Allocates `len` bytes at an offset that is a multiple of `align`.
+    ///
+    /// Blocks are naturally aligned to their own size, so this picks a
+    /// block of at least `align` bytes, unless a free block smaller than
+    /// that already starts on an `align` boundary and is within
+    /// `set_max_split_distance` orders of `len`: then the smallest such
+    /// block is split down to fit `len`. `free` releases the whole block.
+    pub fn alloc_aligned(&mut self, len: usize, align: usize) -> Result<pptr, BuddyError> {
+        let idx = self.order_for(len, align)?;
+        let fit = self.order_of_len(len);
+        let aligned = if fit < idx {
+            let end = usize::min(idx, self.split_limit(fit));
+            self.free.iter()
+                .filter(|&(&off, &(i, _))| fit <= i && i < end && off.is_multiple_of(align))
+                .min_by_key(|&(_, &(i, _))| i)
+                .map(|(&off, &(i, _))| (off, i))
+        } else {
+            None
+        };
+        match aligned {
+            Some((res, from)) => {
+                self.check_reserve(fit)?;
+                if self.poison {
+                    self.check_block_poison(res, from)?;
+                }
+                self.split_down(res, from, fit);
+                self.hand_out(res, fit, len);
+                Ok(res)
+            }
+            None => self.alloc_block(idx, len)
+        }
+    }
+    /// Allocates a whole block of order `order`, or of the minimum order if
+    /// that is larger, and returns its offset. Unlike `alloc`, no length is
+    /// rounded up first: the block is exactly `1 << order` bytes, and with
+    /// red zones on it has none, so the caller may use all of it. Free it
+    /// with `free_exact_order` or `free_ptr`.
+    pub fn alloc_exact_order(&mut self, order: usize) -> Result<pptr, BuddyError> {
+        if !self.is_initialized() {
+            return Err(BuddyError::NotInitialized);
+        }
+        if order >= self.buddies.len() {
+            return Err(BuddyError::SizeOverflow);
+        }
+        let idx = usize::max(order, self.min_order);
+        self.alloc_block(idx, 1 << idx)
+    }
+    /// Frees a block from `alloc_exact_order`. Fails with `SizeMismatch` if
+    /// the allocation at `off` is not a block of `order`, and nothing is
+    /// freed.
+    pub fn free_exact_order(&mut self, off: pptr, order: usize) -> Result<(), BuddyError> {
+        match self.allocated.get(&off) {
+            Some(&idx) if idx != usize::max(order, self.min_order) => Err(BuddyError::SizeMismatch { offset: off }),
+            _ => self.free_ptr(off)
+        }
+    }
+    /// Takes a block of order `idx` for an allocation of `len` bytes.
+    fn alloc_block(&mut self, idx: usize, len: usize) -> Result<pptr, BuddyError> {
+        self.check_reserve(idx)?;
+        if let Some(res) = self.unbin(idx)? {
+            self.hand_out(res, idx, len);
+            return Ok(res);
+        }
+        match self.pick_free(idx) {
+            Some((from, res)) => {
+                if self.poison {
+                    self.check_block_poison(res, from)?;
+                }
+                self.split_down(res, from, idx);
+                self.hand_out(res, idx, len);
+                Ok(res)
+            }
+            None if self.lazy_coalesce && self.coalesce_all() > 0 => self.alloc_block(idx, len),
+            None => Err(BuddyError::OutOfMemory)
+        }
+    }
+    /// Like `alloc`, but takes the free block of the right order that lies
+    /// closest to `hint`, rather than the head of its list. If there is no
+    /// block of exactly that order, a bigger one is split as `alloc` would.
+    pub fn alloc_near(&mut self, len: usize, hint: pptr) -> Result<pptr, BuddyError> {
+        let idx = self.order_for(len, 1)?;
+        self.check_reserve(idx)?;
+        let of_order = |(&off, &(i, _)): (&pptr, &(usize, _))| if i == idx { Some(off) } else { None };
+        let below = self.free.range(..=hint).rev().find_map(of_order);
+        let above = self.free.range(hint..).find_map(of_order);
+        let res = match (below, above) {
+            (Some(b), Some(a)) if a - hint < hint - b => a,
+            (Some(b), _) => b,
+            (None, Some(a)) => a,
+            (None, None) => return self.alloc(len)
+        };
+        if self.poison {
+            self.check_block_poison(res, idx)?;
+        }
+        self.unlink(idx, res);
+        self.hand_out(res, idx, len);
+        Ok(res)
+    }
+    /// Allocates the block of order `idx` at `off` for `len` bytes,
+    /// splitting the free block that holds it down to it, so a logged
+    /// allocation comes back at the same block whatever policy made it.
+    /// Fails with `InvalidPointer` if no such block can exist and with
+    /// `InUse` if it is not wholly free.
+    pub(crate) fn take_block(&mut self, off: pptr, idx: usize, len: usize) -> Result<(), BuddyError> {
+        if idx >= self.buddies.len() || !off.is_multiple_of(1 << idx) || off >= self.size {
+            return Err(BuddyError::InvalidPointer { offset: off });
+        }
+        let (mut res, from) = match self.free.range(..=off).next_back() {
+            Some((&start, &(from, _))) if from >= idx && off - start < 1 << from => (start, from),
+            _ => return Err(BuddyError::InUse { offset: off })
+        };
+        self.unlink(from, res);
+        for k in (idx..from).rev() {
+            self.emit(Event::Split { order: k + 1, offset: res });
+            if off & (1 << k) == 0 {
+                self.push(k, res + (1 << k));
+            } else {
+                self.push(k, res);
+                res += 1 << k;
+            }
+        }
+        self.stats.splits += from - idx;
+        self.hand_out(off, idx, len);
+        Ok(())
+    }
+    /// Keeps `bytes` of free memory back for `alloc_reserved`: any other
+    /// allocation that would leave less than that free fails with
+    /// `OutOfMemory`, so an allocation needed to report running out of
+    /// memory can still succeed. Blocks are taken whole, so the check counts
+    /// the full block an allocation needs.
+    pub fn set_reserved(&mut self, bytes: usize) {
+        self.reserved = bytes;
+    }
+    /// Like `alloc`, but may use the memory kept back by `set_reserved`.
+    pub fn alloc_reserved(&mut self, len: usize) -> Result<pptr, BuddyError> {
+        let reserved = core::mem::replace(&mut self.reserved, 0);
+        let res = self.alloc(len);
+        self.reserved = reserved;
+        res
+    }
+    fn check_reserve(&self, idx: usize) -> Result<(), BuddyError> {
+        match self.available.checked_sub(1 << idx) {
+            Some(left) if left >= self.reserved => Ok(()),
+            _ => Err(BuddyError::OutOfMemory)
+        }
+    }
+    /// Accounts for the block of order `idx` at `res`, just taken off the
+    /// free-lists, as an allocation of `len` bytes.
+    fn hand_out(&mut self, res: pptr, idx: usize, len: usize) {
+        debug_assert!(res < self.size);
+        self.set_available(self.available - (1 << idx));
+        self.set_allocated(res, Some(idx));
+        self.stats.allocs += 1;
+        self.stats.live += 1;
+        self.stats.peak_used = usize::max(self.stats.peak_used, self.used_bytes());
+        self.seal(res, len);
+        self.emit(Event::Alloc { order: idx, offset: res });
+        self.debug_assert_consistent();
+    }
+    /// Allocates `n` contiguous pages of `page_size` bytes, starting at an
+    /// offset that is a multiple of `page_size`. Fails with
+    /// `InvalidAlignment` if `page_size` is not a power of two, and with
+    /// `SizeOverflow` if the pages do not fit in a `usize`.
+    pub fn alloc_pages(&mut self, n: usize, page_size: usize) -> Result<pptr, BuddyError> {
+        let len = n.checked_mul(page_size).ok_or(BuddyError::SizeOverflow)?;
+        self.alloc_aligned(len, page_size)
+    }
+    fn order_for(&self, len: usize, align: usize) -> Result<usize, BuddyError> {
+        if !self.is_initialized() {
+            return Err(BuddyError::NotInitialized);
+        }
+        if len == 0 {
+            return Err(BuddyError::ZeroSize);
+        }
+        if !align.is_power_of_two() {
+            return Err(BuddyError::InvalidAlignment);
+        }
+        if self.redzone && len.checked_add(REDZONE_LEN).is_none() {
+            return Err(BuddyError::SizeOverflow);
+        }
+        let idx = usize::max(self.order_of_len(len), align.trailing_zeros() as usize);
+        if idx >= self.buddies.len() {
+            return Err(BuddyError::SizeOverflow);
+        }
+        Ok(idx)
+    }
+    /// Order of the block that `len` bytes are rounded up to.
+    fn order_of(&self, len: usize) -> usize {
+        usize::max(order_ceil(len), self.min_order)
+    }
+    /// Order of the block `alloc(len)` hands out, counting the minimum
+    /// order and the red zone. `realloc` to a length of the same order as
+    /// the allocation keeps it in place, so comparing the two tells ahead
+    /// of time whether a resize moves the data.
+    pub fn order_of_len(&self, len: usize) -> usize {
+        if self.redzone {
+            self.order_of(len.saturating_add(REDZONE_LEN))
+        } else {
+            self.order_of(len)
+        }
+    }
+    /// Whether allocations of `old_len` and `new_len` bytes take blocks of
+    /// the same order, in which case `realloc` from one to the other keeps
+    /// the block where it is without touching the free-lists. Zero lengths
+    /// have no order and never match.
+    pub fn same_order(&self, old_len: usize, new_len: usize) -> bool {
+        old_len > 0 && new_len > 0 && self.order_of_len(old_len) == self.order_of_len(new_len)
+    }
+    /// Makes sure a free block of order `order` (or the minimum order, if
+    /// that is larger) is ready, splitting a bigger one now if needed, so a
+    /// later `alloc` of that size takes it without splitting.
+    pub fn reserve(&mut self, order: usize) -> Result<(), BuddyError> {
+        if !self.is_initialized() {
+            return Err(BuddyError::NotInitialized);
+        }
+        if order >= self.buddies.len() {
+            return Err(BuddyError::SizeOverflow);
+        }
+        let idx = usize::max(order, self.min_order);
+        if self.buddies[idx].is_none() {
+            let off = self.find_free_memory(idx).ok_or(BuddyError::OutOfMemory)?;
+            self.push(idx, off);
+        }
+        Ok(())
+    }
+    /// Stops `free` from merging blocks of order `order` or above with their
+    /// buddy, so blocks of a size that is freed and allocated over and over
+    /// stay on their list instead of being merged and split again each time.
+    /// Pairs left apart this way are still merged by `coalesce_all`.
+    pub fn set_max_coalesce_order(&mut self, order: usize) {
+        self.max_coalesce_order = order;
+    }
+    /// Keeps every free-list sorted by offset while on, so `alloc` takes
+    /// the lowest free block of an order rather than the one freed last,
+    /// which packs live blocks towards the start of the memory. Each free
+    /// then costs a search for its place in the list. Turning it on sorts
+    /// the lists as they are.
+    pub fn set_address_ordered(&mut self, on: bool) {
+        self.address_ordered = on;
+        if !on {
+            return;
+        }
+        let blocks: Vec<(pptr, usize)> = self.free.iter().map(|(&off, &(idx, _))| (off, idx)).collect();
+        for &(off, idx) in &blocks {
+            self.unlink(idx, off);
+        }
+        for (off, idx) in blocks {
+            self.push(idx, off);
+        }
+    }
+    /// Turns lazy merging on or off. While on, `free` puts a block on its
+    /// list without merging it with its buddy, and merging is left to the
+    /// first allocation that finds no block big enough, which runs
+    /// `coalesce_all` and tries again. Bursts of frees then cost no merges
+    /// that the allocations right after would only split again. `can_alloc`
+    /// and `largest_free_block` only see the lists as they are.
+    pub fn set_lazy_coalesce(&mut self, on: bool) {
+        self.lazy_coalesce = on;
+    }
+    /// Stops an allocation from splitting a block more than `orders` orders
+    /// larger than it needs, so a small request cannot carve up the one big
+    /// block left; it fails with `OutOfMemory` instead and the big block
+    /// stays whole. `compact` is not limited.
+    pub fn set_max_split_distance(&mut self, orders: usize) {
+        self.max_split_distance = orders;
+    }
+    /// Tells whether `alloc(len)` would succeed, without touching any state.
+    pub fn can_alloc(&self, len: usize) -> bool {
+        match self.order_for(len, 1) {
+            Ok(idx) if self.check_reserve(idx).is_err() => false,
+            Ok(idx) => {
+                self.buddies[idx..self.split_limit(idx)].iter().any(Option::is_some) || !self.fast_bins[idx].is_empty()
+            }
+            Err(_) => false
+        }
+    }
+    /// Size of the biggest block that is free right now, or 0 if none is.
+    /// Blocks cached in the fast bins count, as `alloc` hands them out just
+    /// like blocks on the free-lists.
+    pub fn largest_free_block(&self) -> usize {
+        let top = (0..self.buddies.len()).rev().find(|&idx| {
+            self.buddies[idx].is_some() || !self.fast_bins[idx].is_empty()
+        });
+        match top {
+            Some(idx) => 1 << idx,
+            None => 0
+        }
+    }
+    /// The most bytes `alloc_aligned` can hand out right now at an
+    /// alignment of `align`, or 0 if it would fail for any length. That is
+    /// the largest free block of at least `align` bytes or, failing that,
+    /// the largest smaller one that starts on an `align` boundary. The red
+    /// zone, when on, is left out of the size. Panics if `align` is not a
+    /// power of two.
+    pub fn largest_aligned_free(&self, align: usize) -> usize {
+        assert!(align.is_power_of_two(), "Alignment is not a power of two");
+        let min = usize::max(align.trailing_zeros() as usize, self.min_order);
+        let top = (min..self.buddies.len()).rev().find(|&idx| {
+            self.buddies[idx].is_some() || !self.fast_bins[idx].is_empty()
+        });
+        let top = top.or_else(|| {
+            self.free.iter()
+                .filter(|&(&off, &(i, _))| i < min && off.is_multiple_of(align))
+                .map(|(_, &(i, _))| i)
+                .max()
+        });
+        match top {
+            Some(idx) if self.redzone => (1_usize << idx).saturating_sub(REDZONE_LEN),
+            Some(idx) => 1 << idx,
+            None => 0
+        }
+    }
+    /// How scattered the free memory is, from 0.0 when it is all one block
+    /// to nearly 1.0 when it is spread over many small ones. A fully
+    /// allocated memory counts as 0.0.
+    pub fn fragmentation(&self) -> f64 {
+        if self.available == 0 {
+            return 0.0;
+        }
+        1.0 - self.largest_free_block() as f64 / self.available as f64
+    }
+    /// Like `alloc`, but the whole returned block, short of its red zone,
+    /// is filled with zeros rather than whatever a previous owner left
+    /// behind.
+    pub fn alloc_zeroed(&mut self, len: usize) -> Result<pptr, BuddyError> {
+        let off = self.alloc(len)?;
+        let end = off + self.usable_size(off);
+        self.memory[off..end].fill(0);
+        Ok(off)
+    }
+    /// Allocates a zeroed array of `count` elements of `elem_size` bytes,
+    /// failing with `SizeOverflow` rather than wrapping when the product
+    /// does not fit in a `usize`.
+    pub fn alloc_array(&mut self, count: usize, elem_size: usize) -> Result<pptr, BuddyError> {
+        let len = count.checked_mul(elem_size).ok_or(BuddyError::SizeOverflow)?;
+        self.alloc_zeroed(len)
+    }
+    /// Allocates the largest block that holds up to `max_len` bytes, going
+    /// down one order at a time while none is free, and returns it with the
+    /// bytes it holds, as `usable_size` counts them. That is at least
+    /// `max_len` when memory allows, and less otherwise. Fails with
+    /// `OutOfMemory` only if not even a block of the minimum order is free.
+    pub fn alloc_at_most(&mut self, max_len: usize) -> Result<(pptr, usize), BuddyError> {
+        let top = self.order_for(max_len, 1)?;
+        let overhead = if self.redzone { REDZONE_LEN } else { 0 };
+        for idx in (self.min_order..=top).rev() {
+            let len = if idx == top { max_len } else { (1 << idx) - usize::min(overhead, 1 << idx) };
+            if len == 0 {
+                break;
+            }
+            match self.alloc(len) {
+                Ok(off) => return Ok((off, self.usable_size(off))),
+                Err(BuddyError::OutOfMemory) => continue,
+                Err(e) => return Err(e)
+            }
+        }
+        Err(BuddyError::OutOfMemory)
+    }
+    /// Resizes the allocation at `off`, in place when it can: when `new_len`
+    /// rounds to the same order, when it needs a smaller one (the upper
+    /// halves are freed as by `shrink_block`), or when it needs a larger one
+    /// and the buddies the block has to grow into are all free. Otherwise
+    /// the first `min(old_len, new_len)` bytes are moved to a fresh block and
+    /// the old one is freed; never more than the length asked for when the
+    /// block has a red zone, so the zone is not copied. With red zones on, fails with `BufferOverflow`
+    /// and changes nothing if the allocation was written past its requested
+    /// length.
+    pub fn realloc(&mut self, off: pptr, old_len: usize, new_len: usize) -> Result<pptr, BuddyError> {
+        if !self.is_initialized() {
+            return Err(BuddyError::NotInitialized);
+        }
+        let idx = match self.allocated.get(&off) {
+            Some(&idx) => idx,
+            None => return Err(BuddyError::InvalidPointer { offset: off })
+        };
+        self.check_redzone(off, idx)?;
+        if new_len > 0 {
+            let new_idx = self.order_of_len(new_len);
+            if new_idx == idx {
+                self.seal(off, new_len);
+                return Ok(off);
+            }
+            if new_idx < idx {
+                let old_len = usize::min(old_len, self.usable_size(off));
+                self.shrink_block(off, old_len, new_len)?;
+                return Ok(off);
+            }
+            if self.grow_in_place(off, idx, new_idx)? {
+                self.seal(off, new_len);
+                return Ok(off);
+            }
+        }
+        let len = usize::min(usize::min(old_len, new_len), self.usable_size(off));
+        let res = self.alloc(new_len)?;
+        self.copy(res, off, len);
+        let tag = self.set_tag(off, None);
+        self.free_ptr(off)?;
+        if tag.is_some() {
+            self.set_tag(res, tag);
+        }
+        Ok(res)
+    }
+    /// Shrinks the allocation at `off` in place to the smallest order that
+    /// holds `new_len` bytes, handing the upper halves it no longer needs
+    /// back to the free-lists. The first `new_len` bytes stay where they are.
+    /// Does nothing if `new_len` needs the same order. Like `free`, fails
+    /// with `SizeMismatch` if `old_len` does not fit in the allocation.
+    pub fn shrink_block(&mut self, off: pptr, old_len: usize, new_len: usize) -> Result<(), BuddyError> {
+        let idx = match self.allocated.get(&off) {
+            Some(&idx) => idx,
+            None => return Err(BuddyError::InvalidPointer { offset: off })
+        };
+        if old_len > self.usable_size(off) {
+            return Err(BuddyError::SizeMismatch { offset: off });
+        }
+        let new_idx = self.order_for(new_len, 1)?;
+        self.check_redzone(off, idx)?;
+        if new_idx >= idx {
+            return Ok(());
+        }
+        self.set_allocated(off, Some(new_idx));
+        for k in (new_idx..idx).rev() {
+            let tail = off + (1 << k);
+            self.emit(Event::Split { order: k + 1, offset: off });
+            if self.poison {
+                self.memory[tail..tail + (1 << k)].fill(POISON);
+            }
+            self.free_block(tail, k);
+        }
+        self.stats.splits += idx - new_idx;
+        self.seal(off, new_len);
+        Ok(())
+    }
+    /// Turns the allocation of order `idx` at `off` into one of order
+    /// `new_idx` by taking in the free buddies above it, if `off` is aligned
+    /// for that order and every one of them is free. Tells whether it did;
+    /// nothing changes if not.
+    fn grow_in_place(&mut self, off: pptr, idx: usize, new_idx: usize) -> Result<bool, BuddyError> {
+        if new_idx >= self.last || !off.is_multiple_of(1 << new_idx) || self.size - off < 1 << new_idx {
+            return Ok(false);
+        }
+        let free = |k: usize| matches!(self.free.get(&(off + (1 << k))), Some(&(i, _)) if i == k);
+        if !(idx..new_idx).all(free) {
+            return Ok(false);
+        }
+        if self.poison {
+            for k in idx..new_idx {
+                self.check_block_poison(off + (1 << k), k)?;
+            }
+        }
+        for k in idx..new_idx {
+            let buddy = off + (1 << k);
+            self.emit(Event::Merge { order: k, left: off, right: buddy });
+            self.unlink(k, buddy);
+            self.set_available(self.available - (1 << k));
+        }
+        self.stats.merges += new_idx - idx;
+        self.stats.peak_used = usize::max(self.stats.peak_used, self.used_bytes());
+        self.set_allocated(off, Some(new_idx));
+        Ok(true)
+    }
+    /// Copies `len` bytes from `src` to `dst` within the memory, like
+    /// `memmove`: the two ranges may overlap. Panics if either one reaches
+    /// past the end of the memory.
+    pub fn copy(&mut self, dst: pptr, src: pptr, len: usize) {
+        let fits = |off: pptr| off.checked_add(len).is_some_and(|end| end <= self.size);
+        assert!(fits(src) && fits(dst), "Copy is out of bounds");
+        self.memory.copy_within(src..src + len, dst);
+    }
+    /// Views the `len` bytes at `off`. Panics if they reach past the end of
+    /// the memory; in debug builds, also if they are not all inside one
+    /// live allocation.
+    pub fn bytes(&self, off: pptr, len: usize) -> &[u8] {
+        self.check_bytes(off, len);
+        &self.memory[off..off + len]
+    }
+    pub fn bytes_mut(&mut self, off: pptr, len: usize) -> &mut [u8] {
+        self.check_bytes(off, len);
+        &mut self.memory[off..off + len]
+    }
+    fn check_bytes(&self, off: pptr, len: usize) {
+        let end = off.checked_add(len).filter(|&end| end <= self.size);
+        assert!(end.is_some(), "Bytes are out of bounds");
+        debug_assert!(
+            len == 0 || self.block_of(off).is_some_and(|(base, idx)| off + len <= base + (1 << idx)),
+            "Bytes are not inside one allocation"
+        );
+    }
+    /// Frees the allocation at `off`. Its size is the one recorded when it
+    /// was allocated: `len` only has to fit in it, and if it does not, this
+    /// fails with `SizeMismatch` and nothing is freed.
+    pub fn free(&mut self, off: pptr, len: usize) -> Result<(), BuddyError> {
+        if self.allocated.contains_key(&off) && len > self.usable_size(off) {
+            return Err(BuddyError::SizeMismatch { offset: off });
+        }
+        let idx = self.take_allocation(off)?;
+        self.release(off, idx)
+    }
+    /// Frees the allocation at `off` whatever its length. With red zones
+    /// on, fails with `BufferOverflow` if the block was written past its
+    /// requested length; the block is freed all the same.
+    pub fn free_ptr(&mut self, off: pptr) -> Result<(), BuddyError> {
+        let idx = self.take_allocation(off)?;
+        self.release(off, idx)
+    }
+    /// Frees every allocation in `[off, off + len)` at once, so a span that
+    /// was allocated as several adjacent blocks merges back as far as it
+    /// can. The span must be covered exactly by live allocations: if it
+    /// starts, ends or has a gap anywhere else, this fails with
+    /// `InvalidPointer` at that offset and nothing is freed.
+    pub fn free_range(&mut self, off: pptr, len: usize) -> Result<(), BuddyError> {
+        if !self.is_initialized() {
+            return Err(BuddyError::NotInitialized);
+        }
+        let end = off.checked_add(len).ok_or(BuddyError::InvalidPointer { offset: off })?;
+        let mut blocks = vec![];
+        let mut next = off;
+        for (&b, &idx) in self.allocated.range(off..end) {
+            if b != next {
+                break;
+            }
+            blocks.push(b);
+            next = b + (1 << idx);
+        }
+        if next != end || len == 0 {
+            return Err(BuddyError::InvalidPointer { offset: next });
+        }
+        let mut res = Ok(());
+        for b in blocks {
+            let freed = self.free_ptr(b);
+            res = res.and(freed);
+        }
+        res
+    }
+    /// Tells whether `off` lies inside the memory managed by this allocator.
+    pub fn owns(&self, off: pptr) -> bool {
+        off < self.size
+    }
+    /// Removes the allocation starting at `off` and returns its order,
+    /// without touching the free-lists.
+    fn take_allocation(&mut self, off: pptr) -> Result<usize, BuddyError> {
+        if !self.is_initialized() {
+            return Err(BuddyError::NotInitialized);
+        }
+        if !self.owns(off) {
+            return Err(BuddyError::InvalidPointer { offset: off });
+        }
+        match self.set_allocated(off, None) {
+            Some(idx) => Ok(idx),
+            None if self.is_free(off) => Err(BuddyError::DoubleFree { offset: off }),
+            None => Err(BuddyError::InvalidPointer { offset: off })
+        }
+    }
+    fn release(&mut self, off: pptr, idx: usize) -> Result<(), BuddyError> {
+        let overflow = self.check_redzone(off, idx);
+        self.set_requested(off, None);
+        self.set_length(off, None);
+        self.set_tag(off, None);
+        self.stats.frees += 1;
+        self.stats.live = self.stats.live.saturating_sub(1);
+        if self.poison {
+            self.memory[off..off + (1 << idx)].fill(POISON);
+        }
+        self.emit(Event::Free { order: idx, offset: off });
+        if !self.bin(off, idx) {
+            self.free_block(off, idx);
+        }
+        self.debug_assert_consistent();
+        overflow
+    }
+    fn is_free(&self, off: pptr) -> bool {
+        match self.free.range(..=off).next_back() {
+            Some((&b, &(idx, _))) if off < b + (1 << idx) => true,
+            _ => self.is_binned(off)
+        }
+    }
+    pub fn free_blocks(&self) -> FreeBlocks<'_> {
+        FreeBlocks::new(self)
+    }
+    /// Finds the live allocation that `off` points into, which need not be
+    /// its start, as `(base, order)`. Live blocks are kept in a map sorted
+    /// by offset, so this is the last one starting at or before `off`, if
+    /// `off` falls inside it.
+    pub fn block_of(&self, off: pptr) -> Option<(pptr, usize)> {
+        let (&base, &idx) = self.allocated.range(..=off).next_back()?;
+        if off < base + (1 << idx) {
+            Some((base, idx))
+        } else {
+            None
+        }
+    }
+    /// Largest power of two that divides the address of `off`.
+    ///
+    /// No metadata is stored in the blocks, so a block of order `k` starts
+    /// right at its offset, a multiple of `1 << k`. With the memory made by
+    /// `init` the start is aligned to the memory size up to `MAX_ALIGN`, so
+    /// such a block is aligned to `1 << k` up to that bound. Another
+    /// `Backing` only gives what its start address gives.
+    pub fn alignment(&self, off: pptr) -> usize {
+        let addr = self.memory.base_ptr().wrapping_add(off);
+        1 << addr.trailing_zeros()
+    }
+    /// Every allocation that is still live, as `(offset, order)` sorted by
+    /// offset. Called before the allocator is dropped, anything listed here
+    /// was never freed.
+    pub fn report_leaks(&self) -> Vec<(pptr, usize)> {
+        self.allocated.iter().map(|(&off, &idx)| (off, idx)).collect()
+    }
+    /// Calls `f` with the offset, usable length and tag of every live
+    /// allocation, in order of offset. Nothing is allocated on the way.
+    pub fn walk_allocated<F: FnMut(pptr, usize, Option<&str>)>(&self, mut f: F) {
+        for &off in self.allocated.keys() {
+            f(off, self.usable_size(off), self.tag_of(off));
+        }
+    }
+    pub fn size_of(&self, off: pptr) -> usize {
+        match self.allocated.get(&off) {
+            Some(idx) => 1 << idx,
+            None => 0
+        }
+    }
+    /// Offsets count from the start of the memory, so a block of order
+    /// `idx` sits at a multiple of `1 << idx` and its buddy is the block that
+    /// differs from it only in bit `idx`, whatever address the memory has.
+    fn free_block(&mut self, off: pptr, idx: usize) {
+        let len = 1 << idx;
+        debug_assert_eq!(off % len, 0, "Block is not aligned to its order");
+        let buddy = off ^ len;
+        let merge = !self.lazy_coalesce && idx < self.last && idx + 1 < self.buddies.len() && idx < self.max_coalesce_order
+            && matches!(self.free.get(&buddy), Some((i, _)) if *i == idx);
+        if merge {
+            let (left, right) = (pptr::min(off, buddy), pptr::max(off, buddy));
+            self.emit(Event::Merge { order: idx, left, right });
+            self.unlink(idx, buddy);
+            self.set_available(self.available - len);
+            self.stats.merges += 1;
+            self.free_block(left, idx + 1);
+            return;
+        }
+        self.set_available(self.available + len);
+        self.push(idx, off);
+    }
+    fn set_head(&mut self, idx: usize, head: Option<Rc<RefCell<Buddy>>>) {
+        let old = core::mem::replace(&mut self.buddies[idx], head);
+        if let Some(log) = &mut self.log {
+            log.push(Undo::Head(idx, old));
+        }
+    }
+    fn set_next(&mut self, node: &Rc<RefCell<Buddy>>, next: Option<Rc<RefCell<Buddy>>>) {
+        let old = core::mem::replace(&mut node.borrow_mut().next, next);
+        if let Some(log) = &mut self.log {
+            log.push(Undo::Next(node.clone(), old));
+        }
+    }
+    fn set_prev(&mut self, node: &Rc<RefCell<Buddy>>, prev: Weak<RefCell<Buddy>>) {
+        let old = core::mem::replace(&mut node.borrow_mut().prev, prev);
+        if let Some(log) = &mut self.log {
+            log.push(Undo::Prev(node.clone(), old));
+        }
+    }
+    fn set_free(&mut self, off: pptr, entry: Option<(usize, Rc<RefCell<Buddy>>)>) {
+        let old = match entry {
+            Some(entry) => self.free.insert(off, entry),
+            None => self.free.remove(&off)
+        };
+        if let Some(log) = &mut self.log {
+            log.push(Undo::Free(off, old));
+        }
+    }
+    fn set_available(&mut self, available: usize) {
+        if let Some(log) = &mut self.log {
+            log.push(Undo::Available(self.available));
+        }
+        self.available = available;
+    }
+    fn set_allocated(&mut self, off: pptr, idx: Option<usize>) -> Option<usize> {
+        let old = match idx {
+            Some(idx) => self.allocated.insert(off, idx),
+            None => self.allocated.remove(&off)
+        };
+        if let Some(log) = &mut self.log {
+            log.push(Undo::Allocated(off, old));
+        }
+        old
+    }
+    fn set_length(&mut self, off: pptr, len: Option<usize>) {
+        let old = match len {
+            Some(len) => self.lengths.insert(off, len),
+            None => self.lengths.remove(&off)
+        };
+        if let Some(log) = &mut self.log {
+            log.push(Undo::Length(off, old));
+        }
+    }
+    fn set_requested(&mut self, off: pptr, len: Option<usize>) {
+        let old = match len {
+            Some(len) => self.requested.insert(off, len),
+            None => self.requested.remove(&off)
+        };
+        if let Some(log) = &mut self.log {
+            log.push(Undo::Requested(off, old));
+        }
+    }
+    fn set_tag(&mut self, off: pptr, tag: Option<String>) -> Option<String> {
+        let old = match tag {
+            Some(tag) => self.tags.insert(off, tag),
+            None => self.tags.remove(&off)
+        };
+        if let Some(log) = &mut self.log {
+            log.push(Undo::Tag(off, old.clone()));
+        }
+        old
+    }
+    /// Starts recording every change to the free-lists and the allocation
+    /// map so that `tx_abort` can undo them. Only the bookkeeping is logged;
+    /// bytes written into the memory are not restored.
+    pub fn tx_begin(&mut self) {
+        assert!(self.log.is_none(), "A transaction is already in progress");
+        self.flush_fast_bins();
+        self.log = Some(vec![Undo::Stats(self.stats)]);
+    }
+    /// Keeps the changes made since `tx_begin`.
+    pub fn tx_end(&mut self) {
+        self.log.take().expect("No transaction in progress");
+    }
+    /// Rolls the free-lists and the allocation map, with the lengths and
+    /// tags of the allocations and the counters, back to where they were at
+    /// `tx_begin`.
+    pub fn tx_abort(&mut self) {
+        let log = self.log.take().expect("No transaction in progress");
+        for undo in log.into_iter().rev() {
+            match undo {
+                Undo::Head(idx, old) => self.buddies[idx] = old,
+                Undo::Next(node, old) => node.borrow_mut().next = old,
+                Undo::Prev(node, old) => node.borrow_mut().prev = old,
+                Undo::Free(off, Some(entry)) => {
+                    self.free.insert(off, entry);
+                }
+                Undo::Free(off, None) => {
+                    self.free.remove(&off);
+                }
+                Undo::Available(old) => self.available = old,
+                Undo::Allocated(off, Some(idx)) => {
+                    self.allocated.insert(off, idx);
+                }
+                Undo::Allocated(off, None) => {
+                    self.allocated.remove(&off);
+                }
+                Undo::Length(off, old) => restore(&mut self.lengths, off, old),
+                Undo::Requested(off, old) => restore(&mut self.requested, off, old),
+                Undo::Tag(off, old) => restore(&mut self.tags, off, old),
+                Undo::Stats(old) => self.stats = old
+            }
+        }
+    }
+    /// Moves every live allocation toward the start of the memory so that
+    /// the free space left behind merges into as few blocks as possible.
+    /// Blocks are placed largest first, which packs them without gaps, and
+    /// their bytes move with them.
+    ///
+    /// This invalidates the offsets of the blocks that move: `relocate` is
+    /// called with `(old, new)` for each one so the caller can fix up its
+    /// pointers. Panics if a transaction is in progress.
+    pub fn compact<F: FnMut(pptr, pptr)>(&mut self, mut relocate: F) {
+        assert!(self.log.is_none(), "Cannot compact during a transaction");
+        let mut live: Vec<(pptr, usize)> = self.allocated.iter().map(|(&off, &idx)| (off, idx)).collect();
+        live.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let mut saved = Vec::with_capacity(self.used_bytes());
+        for &(off, idx) in &live {
+            saved.extend_from_slice(&self.memory[off..off + (1 << idx)]);
+        }
+        let stats = self.stats;
+        let requested = core::mem::take(&mut self.requested);
+        let lengths = core::mem::take(&mut self.lengths);
+        let mut tags = core::mem::take(&mut self.tags);
+        let distance = core::mem::replace(&mut self.max_split_distance, usize::MAX);
+        self.lay_out();
+        let mut data = &saved[..];
+        for (old, idx) in live {
+            let off = self.find_free_memory(idx).expect("Live blocks no longer fit");
+            self.available -= 1 << idx;
+            self.allocated.insert(off, idx);
+            let (bytes, rest) = data.split_at(1 << idx);
+            self.memory[off..off + bytes.len()].copy_from_slice(bytes);
+            data = rest;
+            if let Some(&len) = requested.get(&old) {
+                self.requested.insert(off, len);
+            }
+            if let Some(&len) = lengths.get(&old) {
+                self.lengths.insert(off, len);
+            }
+            if let Some(tag) = tags.remove(&old) {
+                self.tags.insert(off, tag);
+            }
+            if off != old {
+                relocate(old, off);
+            }
+        }
+        self.max_split_distance = distance;
+        self.stats = stats;
+    }
+    /// Merges every pair of free buddies that sit apart on the same list
+    /// and returns how many merges that took. `free` merges as it goes, so
+    /// such pairs only come from free-lists built some other way, such as an
+    /// image saved by an older version. Live blocks are never moved.
+    pub fn coalesce_all(&mut self) -> usize {
+        self.flush_fast_bins();
+        let mut merges = 0;
+        for idx in 0..self.buddies.len() - 1 {
+            let offs: BTreeSet<pptr> = self.free_blocks()
+                .filter(|&(i, _, _)| i == idx)
+                .map(|(_, off, _)| off)
+                .collect();
+            for &off in &offs {
+                let buddy = off ^ (1 << idx);
+                if off < buddy && offs.contains(&buddy) {
+                    self.emit(Event::Merge { order: idx, left: off, right: buddy });
+                    self.unlink(idx, off);
+                    self.unlink(idx, buddy);
+                    self.push(idx + 1, off);
+                    merges += 1;
+                }
+            }
+        }
+        self.stats.merges += merges;
+        merges
+    }
+    /// Throws the free-lists away and builds them again from the live
+    /// allocations alone: every gap between them becomes the fewest aligned
+    /// free blocks that cover it, so buddies come out merged. This recovers
+    /// a heap whose lists `validate` rejects, as long as the allocation map
+    /// is still right. Cached fast-bin blocks go back on the lists too, and
+    /// with poisoning on the free memory is poisoned again.
+    ///
+    /// Panics if a transaction is in progress.
+    pub fn rebuild_free_lists(&mut self) {
+        assert!(self.log.is_none(), "Cannot rebuild during a transaction");
+        for b in self.buddies.iter_mut() {
+            *b = None;
+        }
+        self.free.clear();
+        for bin in self.fast_bins.iter_mut() {
+            bin.clear();
+        }
+        let live: Vec<(pptr, usize)> = self.allocated.iter().map(|(&off, &idx)| (off, idx)).collect();
+        let mut gaps = vec![];
+        let mut start = 0;
+        for (off, idx) in live {
+            if start < off {
+                gaps.push((start, off));
+            }
+            start = usize::max(start, off + (1 << idx));
+        }
+        if start < self.size {
+            gaps.push((start, self.size));
+        }
+        self.available = 0;
+        for (start, end) in gaps {
+            if self.poison {
+                self.memory[start..end].fill(POISON);
+            }
+            for (idx, off) in self.aligned_blocks(start, end) {
+                self.push(idx, off);
+            }
+            self.available += end - start;
+        }
+    }
+    /// Bytes currently sitting in free blocks. Free-list metadata is kept
+    /// outside the arena, so this is `size` minus the full block size of
+    /// every live allocation, and returns to `size` once all are freed.
+    pub fn available(&self) -> usize {
+        self.available
+    }
+    /// Checks the free-lists and the allocation map against each other:
+    /// every block must be of an order there is a list for, in bounds,
+    /// aligned to its order and disjoint from the rest, and together they
+    /// must cover the whole memory. Never panics, however the metadata was
+    /// made, so it can vet an untrusted snapshot or image.
+    pub fn validate(&self) -> Result<(), CorruptionError> {
+        let top = if self.size == 0 { 0 } else { self.top_order() + 1 };
+        if self.last > top || (self.size != 0 && self.last <= self.min_order) {
+            return Err(CorruptionError::InvalidLast { last: self.last });
+        }
+        // Every block as `(offset, order, free)`.
+        let mut blocks = vec!();
+        let mut free_blocks = self.free_blocks();
+        for (idx, off, _) in &mut free_blocks {
+            blocks.push((off, idx, true));
+        }
+        if let Some((idx, off)) = free_blocks.overrun {
+            return Err(CorruptionError::Cycle { offset: off, order: idx });
+        }
+        blocks.extend(self.binned().map(|(idx, off)| (off, idx, true)));
+        blocks.extend(self.allocated.iter().map(|(&off, &idx)| (off, idx, false)));
+        for &(off, idx, _) in &blocks {
+            if idx >= self.buddies.len() {
+                return Err(CorruptionError::InvalidOrder { offset: off, order: idx });
+            }
+            if off >= self.size || self.size - off < 1 << idx {
+                return Err(CorruptionError::OutOfBounds { offset: off, order: idx });
+            }
+            if off & ((1 << idx) - 1) != 0 {
+                return Err(CorruptionError::Misaligned { offset: off, order: idx });
+            }
+        }
+        if self.poison {
+            for &(off, idx, _) in blocks.iter().filter(|b| b.2) {
+                if let Some(pos) = self.memory[off..off + (1 << idx)].iter().position(|&b| b != POISON) {
+                    return Err(CorruptionError::WriteAfterFree { offset: off + pos, order: idx });
+                }
+            }
+        }
+        blocks.sort_unstable();
+        for w in blocks.windows(2) {
+            if w[0].0 + (1 << w[0].1) > w[1].0 {
+                return Err(CorruptionError::Overlap { offset: w[1].0, order: w[1].1 });
+            }
+        }
+        // The blocks are disjoint and in bounds, so neither sum overflows.
+        let free: usize = blocks.iter().filter(|b| b.2).map(|b| 1 << b.1).sum();
+        let used: usize = blocks.iter().filter(|b| !b.2).map(|b| 1 << b.1).sum();
+        if free != self.available {
+            return Err(CorruptionError::AvailableMismatch { recorded: self.available, actual: free });
+        }
+        if free + used != self.size {
+            return Err(CorruptionError::Unaccounted { accounted: free + used, size: self.size });
+        }
+        Ok(())
+    }
+    /// The cheap part of `validate`, run after every `alloc` and `free` in
+    /// debug builds: the free-lists must end, stay in bounds and add up to
+    /// `available`. Compiled out in release builds.
+    fn debug_assert_consistent(&self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        let mut blocks = self.free_blocks();
+        for (idx, off, len) in &mut blocks {
+            assert!(off < self.size && self.size - off >= len, "Free block {} of order {} is out of bounds", off, idx);
+        }
+        if let Some((idx, off)) = blocks.overrun {
+            panic!("Free-list {} loops back through block {}", idx, off);
+        }
+        assert_eq!(self.recompute_available(), self.available, "Free-lists do not add up to the available bytes");
+    }
+    /// Bytes in free blocks, counted by walking the free-lists and the fast
+    /// bins rather than taken from the running total that `available`
+    /// returns. The two are equal unless the accounting has gone wrong,
+    /// which debug builds check after every `alloc` and `free`.
+    pub fn recompute_available(&self) -> usize {
+        let listed: usize = self.free_blocks().map(|(_, _, len)| len).sum();
+        listed + self.binned().map(|(idx, _)| 1 << idx).sum::<usize>()
+    }
+    /// Total bytes managed by the allocator.
+    pub fn capacity(&self) -> usize {
+        self.size
+    }
+    /// Bytes in free blocks, the same figure as `available`.
+    pub fn free_bytes(&self) -> usize {
+        self.available
+    }
+    /// Bytes taken by live allocations, counted at their full block size.
+    /// Metadata is not stored in the memory, so there is no overhead and
+    /// `used_bytes() + free_bytes() == capacity()`.
+    pub fn used_bytes(&self) -> usize {
+        self.size - self.available
+    }
+    #[cfg(feature = "std")]
+    pub fn print(&self) {
+        self.write_state(&mut io::stdout()).expect("Failed to print the free-lists");
+    }
+    /// Writes one line per order from the minimum order up to the largest,
+    /// with the free blocks on its list, followed by the bytes available.
+    /// Writes a single line saying so if the allocator has no memory yet.
+    #[cfg(feature = "std")]
+    pub fn write_state<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        if !self.is_initialized() {
+            return writeln!(w, "Memory is not initialized");
+        }
+        writeln!(w)?;
+        let mut blocks = self.free_blocks().peekable();
+        for idx in self.min_order..self.last {
+            write!(w, "{:>6} [{:>2}] ", 1 << idx, idx)?;
+            while let Some((_, off, len)) = blocks.next_if(|b| b.0 == idx) {
+                write!(w, "({}..{})", off, off + len - 1)?;
+            }
+            writeln!(w)?;
+        }
+        writeln!(w, "Available = {} bytes", self.available)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heap(size: usize) -> BuddyAllocator {
+        let mut heap = BuddyAllocator::new();
+        heap.init(size);
+        heap
+    }
+
+    #[test]
+    fn alloc_aligned_uses_a_smaller_aligned_block() {
+        let mut heap = heap(1024);
+        heap.alloc(32).unwrap();
+        heap.alloc(32).unwrap();
+        let c = heap.alloc(32).unwrap();
+        heap.alloc(32).unwrap();
+        heap.alloc(128).unwrap();
+        heap.alloc(256).unwrap();
+        heap.alloc(512).unwrap();
+        heap.free_ptr(c).unwrap();
+        assert_eq!(c, 64);
+        assert_eq!(heap.largest_aligned_free(64), 32);
+        assert_eq!(heap.largest_aligned_free(128), 0);
+        assert_eq!(heap.alloc_aligned(24, 64), Ok(c));
+        assert_eq!(heap.size_of(c), 32);
+        assert_eq!(heap.validate(), Ok(()));
+    }
+
+    #[test]
+    fn alloc_aligned_respects_split_distance() {
+        let mut heap = heap(1024);
+        heap.alloc(512).unwrap();
+        heap.alloc(128).unwrap();
+        heap.alloc(128).unwrap();
+        heap.set_max_split_distance(1);
+        // Free now: one 256-byte block at 768, aligned to 256 but four
+        // orders above a 16-byte request.
+        assert_eq!(heap.alloc_aligned(16, 512), Err(BuddyError::OutOfMemory));
+        assert_eq!(heap.available(), 256);
+        assert!(heap.alloc_aligned(100, 256).is_ok());
+    }
+
+    #[test]
+    fn alloc_at_most_on_a_nearly_full_heap() {
+        let mut heap = heap(1024);
+        heap.alloc(512).unwrap();
+        heap.alloc(256).unwrap();
+        heap.alloc(128).unwrap();
+        heap.alloc(64).unwrap();
+        let (off, len) = heap.alloc_at_most(300).unwrap();
+        assert_eq!((off, len), (960, 64));
+        assert_eq!(heap.size_of(off), 64);
+        assert_eq!(heap.alloc_at_most(300), Err(BuddyError::OutOfMemory));
+        assert_eq!(heap.validate(), Ok(()));
+    }
+
+    #[test]
+    fn alloc_at_most_with_red_zones() {
+        let mut heap = heap(128);
+        heap.set_redzone(true);
+        let (off, len) = heap.alloc_at_most(100).unwrap();
+        assert_eq!((off, len), (0, 100));
+        assert_eq!(heap.size_of(off), 128);
+        let mut heap = self::heap(1024);
+        heap.set_redzone(true);
+        let (off, len) = heap.alloc_at_most(100).unwrap();
+        assert_eq!(len, 100);
+        assert_eq!(heap.size_of(off), 128);
+        heap.alloc(500).unwrap();
+        heap.alloc(200).unwrap();
+        let (off, len) = heap.alloc_at_most(200).unwrap();
+        assert_eq!(len, 128 - REDZONE_LEN);
+        assert_eq!(heap.size_of(off), 128);
+    }
+
+    #[test]
+    fn same_order_around_a_power_of_two() {
+        let mut heap = heap(1024);
+        assert!(heap.same_order(65, 128));
+        assert!(!heap.same_order(64, 65));
+        assert!(!heap.same_order(128, 129));
+        assert!(!heap.same_order(0, 1));
+        heap.set_redzone(true);
+        assert!(!heap.same_order(100, 128));
+        assert!(heap.same_order(100, 128 - REDZONE_LEN));
+    }
+
+    #[test]
+    fn realloc_in_place_up_to_the_block_size() {
+        let mut heap = heap(1024);
+        let a = heap.alloc(100).unwrap();
+        heap.alloc(128).unwrap();
+        let free = heap.snapshot().free_lists;
+        assert_eq!(heap.realloc(a, 100, 128), Ok(a));
+        assert_eq!(heap.realloc(a, 128, 65), Ok(a));
+        assert_eq!(heap.snapshot().free_lists, free);
+        let moved = heap.realloc(a, 65, 129).unwrap();
+        assert_ne!(moved, a);
+        assert_eq!(heap.size_of(moved), 256);
+        assert_eq!(heap.validate(), Ok(()));
+    }
+
+    #[test]
+    fn from_slice_over_a_leaked_buffer() {
+        let buf: &'static mut [u8] = Box::leak(Box::new([0u8; 4096]));
+        let base = buf.as_ptr() as usize;
+        let mut heap = BuddyAllocator::from_slice(buf);
+        assert_eq!(heap.capacity(), 4096);
+        let a = heap.alloc(100).unwrap();
+        heap.bytes_mut(a, 100).fill(9);
+        assert_eq!(heap.memory.base_ptr(), base);
+        assert_eq!(heap.bytes(a, 100), &[9; 100][..]);
+        heap.free_ptr(a).unwrap();
+        assert_eq!(heap.available(), 4096);
+    }
+
+    #[test]
+    fn tx_end_keeps_changes() {
+        let mut heap = heap(1024);
+        let a = heap.alloc(100).unwrap();
+        heap.tx_begin();
+        let b = heap.alloc(200).unwrap();
+        heap.free_ptr(a).unwrap();
+        heap.tx_end();
+        assert_eq!(heap.report_leaks(), vec![(b, 8)]);
+        assert_eq!(heap.available(), 768);
+        assert_eq!(heap.stats().allocs, 2);
+    }
+
+    #[test]
+    fn tx_abort_restores_heap_and_stats() {
+        let mut heap = heap(1024);
+        let a = heap.alloc(100).unwrap();
+        let before = heap.snapshot();
+        let stats = heap.stats();
+        heap.tx_begin();
+        heap.alloc(200).unwrap();
+        heap.free_ptr(a).unwrap();
+        heap.alloc(16).unwrap();
+        heap.tx_abort();
+        assert_eq!(heap.snapshot(), before);
+        assert_eq!(heap.stats(), stats);
+        assert_eq!(heap.validate(), Ok(()));
+    }
+
+    #[test]
+    fn reset_is_refused_in_a_transaction() {
+        let mut heap = heap(1024);
+        heap.alloc(100).unwrap();
+        heap.tx_begin();
+        assert_eq!(heap.reset(), Err(BuddyError::InTransaction));
+        heap.tx_end();
+        heap.reset().unwrap();
+        assert_eq!(heap.available(), 1024);
+    }
+
+    #[test]
+    fn tx_abort_restores_tags() {
+        let mut heap = heap(1024);
+        let kept = heap.alloc_tagged(16, "kept").unwrap();
+        heap.tx_begin();
+        let dropped = heap.alloc_tagged(16, "x").unwrap();
+        heap.free_ptr(kept).unwrap();
+        heap.tx_abort();
+        assert_eq!(heap.tag_of(dropped), None);
+        assert_eq!(heap.tag_of(kept), Some("kept"));
+        let untagged = heap.alloc(16).unwrap();
+        assert_eq!(heap.tag_of(untagged), None);
+        assert!(BuddyAllocator::restore(&heap.snapshot()).is_some());
+    }
+
+    #[test]
+    fn grow_adds_free_blocks() {
+        let mut heap = heap(1024);
+        let a = heap.alloc(512).unwrap();
+        heap.grow(1024).unwrap();
+        assert_eq!(heap.capacity(), 2048);
+        assert_eq!(heap.available(), 1536);
+        assert_eq!(heap.validate(), Ok(()));
+        heap.free_ptr(a).unwrap();
+        assert_eq!(heap.largest_free_block(), 2048);
+    }
+
+    #[test]
+    fn failed_shrink_changes_nothing() {
+        let mut heap = heap(1024);
+        heap.set_fast_bins(6, 4);
+        let a = heap.alloc(16).unwrap();
+        heap.alloc(16).unwrap();
+        heap.alloc(300).unwrap();
+        heap.free_ptr(a).unwrap();
+        let before = heap.snapshot();
+        assert!(matches!(heap.shrink_to(512), Err(BuddyError::InUse { .. })));
+        assert_eq!(heap.snapshot(), before);
+        assert_eq!(heap.binned().count(), 1);
+    }
+
+    #[test]
+    fn shrink_keeps_the_lower_part() {
+        let mut heap = heap(1024);
+        heap.alloc(100).unwrap();
+        heap.shrink_to(300).unwrap();
+        assert_eq!(heap.capacity(), 300);
+        assert_eq!(heap.available(), 172);
+        assert_eq!(heap.validate(), Ok(()));
+        heap.tx_begin();
+        assert_eq!(heap.shrink_to(256), Err(BuddyError::InTransaction));
+        heap.tx_end();
+    }
+
+    #[test]
+    fn grow_is_refused_in_a_transaction() {
+        let mut heap = heap(1024);
+        heap.tx_begin();
+        assert_eq!(heap.grow(1024), Err(BuddyError::InTransaction));
+        heap.tx_abort();
+        assert_eq!(heap.capacity(), 1024);
+        assert_eq!(heap.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_finds_a_free_list_cycle() {
+        let mut heap = heap(1024);
+        heap.alloc(64).unwrap();
+        assert_eq!(heap.validate(), Ok(()));
+        let head = heap.buddies[6].clone().unwrap();
+        head.borrow_mut().next = Some(head.clone());
+        assert!(matches!(heap.validate(), Err(CorruptionError::Cycle { order: 6, .. })));
+        head.borrow_mut().next = None;
+        assert_eq!(heap.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_finds_bad_blocks() {
+        let mut heap = heap(1024);
+        let a = heap.alloc(64).unwrap();
+        heap.allocated.insert(1024, 6);
+        assert_eq!(heap.validate(), Err(CorruptionError::OutOfBounds { offset: 1024, order: 6 }));
+        heap.allocated.remove(&1024);
+        heap.allocated.remove(&a);
+        heap.allocated.insert(a + 32, 6);
+        assert_eq!(heap.validate(), Err(CorruptionError::Misaligned { offset: 32, order: 6 }));
+        heap.allocated.remove(&(a + 32));
+        heap.allocated.insert(a, 7);
+        assert_eq!(heap.validate(), Err(CorruptionError::Overlap { offset: 64, order: 6 }));
+    }
+
+    #[test]
+    fn fragmentation_rises_on_a_checkerboard() {
+        let mut heap = heap(1024);
+        assert_eq!(heap.fragmentation(), 0.0);
+        let blocks: Vec<pptr> = (0..16).map(|_| heap.alloc(64).unwrap()).collect();
+        assert_eq!(heap.fragmentation(), 0.0);
+        for &off in blocks.iter().step_by(2) {
+            heap.free_ptr(off).unwrap();
+        }
+        assert_eq!(heap.fragmentation(), 0.875);
+        for &off in blocks.iter().skip(1).step_by(2) {
+            heap.free_ptr(off).unwrap();
+        }
+        assert_eq!(heap.fragmentation(), 0.0);
+    }
+
+    #[test]
+    fn order_rounding() {
+        for (x, ceil, floor) in [(0, 0, 0), (1, 0, 0), (2, 1, 1), (3, 2, 1), (4, 2, 2), (5, 3, 2), (1023, 10, 9), (1024, 10, 10), (1025, 11, 10)] {
+            assert_eq!(order_ceil(x), ceil, "order_ceil({})", x);
+            assert_eq!(order_floor(x), floor, "order_floor({})", x);
+        }
+        assert_eq!(order_ceil(usize::MAX), num_bits::<usize>() as usize);
+        assert_eq!(order_floor(usize::MAX), num_bits::<usize>() as usize - 1);
+    }
+
+    /// xorshift64, so the randomized tests need no crates and run the same
+    /// sequences every time.
+    fn xorshift(state: &mut u64) -> u64 {
+        let mut x = *state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        *state = x;
+        x
+    }
+
+    #[test]
+    fn live_allocations_never_overlap() {
+        for seed in 1..=32 {
+            let mut rng = seed;
+            let mut heap = BuddyAllocator::new();
+            heap.init_with(vec![0u8; 4096], 0);
+            let mut live: Vec<(pptr, usize)> = vec![];
+            for _ in 0..500 {
+                if live.is_empty() || !xorshift(&mut rng).is_multiple_of(3) {
+                    let len = 1 + (xorshift(&mut rng) % 300) as usize;
+                    match heap.alloc(len) {
+                        Ok(off) => {
+                            let block = heap.size_of(off);
+                            assert!(block >= len, "seed {}: block {} of {} bytes for {}", seed, off, block, len);
+                            assert!(off.is_multiple_of(block) && off + block <= heap.capacity(), "seed {}: block {}", seed, off);
+                            for &(o, _) in &live {
+                                let b = heap.size_of(o);
+                                assert!(off + block <= o || o + b <= off, "seed {}: {} overlaps {}", seed, off, o);
+                            }
+                            live.push((off, len));
+                        }
+                        Err(e) => assert_eq!(e, BuddyError::OutOfMemory, "seed {}", seed)
+                    }
+                } else {
+                    let (off, len) = live.swap_remove(xorshift(&mut rng) as usize % live.len());
+                    heap.free(off, len).unwrap();
+                }
+                assert_eq!(heap.validate(), Ok(()), "seed {}", seed);
+            }
+            for (off, len) in live.drain(..) {
+                heap.free(off, len).unwrap();
+            }
+            assert_eq!(heap.available(), 4096);
+            assert_eq!(heap.free_blocks().map(|(idx, off, _)| (idx, off)).collect::<Vec<_>>(), vec![(12, 0)]);
+        }
+    }
+
+    /// The operations of the fuzz target in `fuzz/`, driven by xorshift.
+    #[test]
+    fn used_bytes_follows_the_live_blocks() {
+        for seed in 1..=32 {
+            let mut rng = seed;
+            let mut heap = BuddyAllocator::new();
+            heap.init_with(vec![0u8; 1 << 16], 0);
+            let mut live: Vec<(pptr, usize)> = vec![];
+            for _ in 0..500 {
+                let len = 1 + (xorshift(&mut rng) % 4096) as usize;
+                let i = match live.len() {
+                    0 => 0,
+                    n => xorshift(&mut rng) as usize % n
+                };
+                match xorshift(&mut rng) % 4 {
+                    0 | 1 if live.is_empty() => {}
+                    0 => {
+                        let (off, len) = live.swap_remove(i);
+                        heap.free(off, len).unwrap();
+                    }
+                    1 => {
+                        let (off, old) = live[i];
+                        if let Ok(res) = heap.realloc(off, old, len) {
+                            live[i] = (res, len);
+                        }
+                    }
+                    2 if !live.is_empty() => {
+                        let (off, old) = live[i];
+                        let len = usize::min(old, len);
+                        heap.shrink_block(off, old, len).unwrap();
+                        live[i] = (off, len);
+                    }
+                    _ => {
+                        if let Ok(off) = heap.alloc(len) {
+                            live.push((off, len));
+                        }
+                    }
+                }
+                let used: usize = live.iter().map(|&(off, _)| heap.size_of(off)).sum();
+                assert_eq!(used, heap.used_bytes(), "seed {}", seed);
+                assert_eq!(heap.validate(), Ok(()), "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Free-lists do not add up to the available bytes")]
+    fn alloc_checks_the_free_lists_in_debug_builds() {
+        let mut heap = heap(1024);
+        heap.available += 64;
+        let _ = heap.alloc(64);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "is out of bounds")]
+    fn free_checks_the_free_lists_in_debug_builds() {
+        let mut heap = heap(1024);
+        let a = heap.alloc(64).unwrap();
+        heap.alloc(64).unwrap();
+        heap.buddies[9].as_ref().unwrap().borrow_mut().off = 2048;
+        let _ = heap.free_ptr(a);
+    }
+
+    #[test]
+    fn huge_lengths_fail_cleanly() {
+        let mut heap = heap(1024);
+        for len in [usize::MAX, usize::MAX - 1, 1 << (num_bits::<usize>() - 1)] {
+            assert_eq!(heap.alloc(len), Err(BuddyError::SizeOverflow), "alloc({})", len);
+            assert_eq!(heap.alloc_aligned(len, 8), Err(BuddyError::SizeOverflow), "alloc_aligned({})", len);
+        }
+        assert_eq!(heap.alloc_array(usize::MAX, 2), Err(BuddyError::SizeOverflow));
+        assert_eq!(heap.alloc(1025), Err(BuddyError::OutOfMemory));
+        let a = heap.alloc(1023).unwrap();
+        assert_eq!(heap.realloc(a, 1023, usize::MAX), Err(BuddyError::SizeOverflow));
+        assert_eq!(heap.free(a, usize::MAX), Err(BuddyError::SizeMismatch { offset: a }));
+        assert_eq!(heap.free(usize::MAX, 1), Err(BuddyError::InvalidPointer { offset: usize::MAX }));
+        heap.free(a, 1023).unwrap();
+        heap.set_redzone(true);
+        assert_eq!(heap.alloc(usize::MAX - 4), Err(BuddyError::SizeOverflow));
+        assert_eq!(heap.validate(), Ok(()));
+    }
+
+    #[test]
+    fn largest_aligned_free_skips_misaligned_blocks() {
+        let mut heap = heap(2048);
+        heap.alloc(512).unwrap();
+        heap.alloc(1024).unwrap();
+        assert_eq!(heap.largest_free_block(), 512);
+        assert_eq!(heap.largest_aligned_free(512), 512);
+        assert_eq!(heap.largest_aligned_free(1024), 0);
+        assert_eq!(heap.alloc_aligned(1, 1024), Err(BuddyError::OutOfMemory));
+    }
+
+    #[test]
+    #[should_panic(expected = "Alignment is not a power of two")]
+    fn largest_aligned_free_wants_a_power_of_two() {
+        heap(1024).largest_aligned_free(48);
+    }
+
+    #[test]
+    fn rebuild_free_lists_repairs_a_bad_next_pointer() {
+        let mut heap = heap(1024);
+        let a = heap.alloc(64).unwrap();
+        let b = heap.alloc(200).unwrap();
+        let other = heap.buddies[7].clone();
+        heap.buddies[6].as_ref().unwrap().borrow_mut().next = other;
+        assert!(heap.validate().is_err());
+        heap.rebuild_free_lists();
+        assert_eq!(heap.validate(), Ok(()));
+        assert_eq!(heap.available(), 1024 - 64 - 256);
+        heap.free(a, 64).unwrap();
+        heap.free(b, 200).unwrap();
+        assert_eq!(heap.largest_free_block(), 1024);
+    }
+
+    #[test]
+    fn double_free_is_reported() {
+        let mut heap = heap(1024);
+        let a = heap.alloc(100).unwrap();
+        let b = heap.alloc(100).unwrap();
+        heap.free_ptr(a).unwrap();
+        let available = heap.available();
+        assert_eq!(heap.free_ptr(a), Err(BuddyError::DoubleFree { offset: a }));
+        assert_eq!(heap.free(a, 100), Err(BuddyError::DoubleFree { offset: a }));
+        assert_eq!(heap.available(), available);
+        assert_eq!(heap.validate(), Ok(()));
+        assert_eq!(heap.alloc(100), Ok(a));
+        let c = heap.alloc(100).unwrap();
+        assert!(c != a && c != b);
+    }
+
+    #[test]
+    fn alloc_zeroed_clears_old_bytes() {
+        let mut heap = heap(1024);
+        let a = heap.alloc(100).unwrap();
+        heap.bytes_mut(a, 128).fill(0xAA);
+        heap.free_ptr(a).unwrap();
+        let b = heap.alloc_zeroed(100).unwrap();
+        assert_eq!(b, a);
+        assert!(heap.bytes(b, 128).iter().all(|&x| x == 0));
+    }
+
+    #[test]
+    fn reset_matches_a_fresh_heap() {
+        let mut heap = heap(4096);
+        for len in [10, 200, 64, 300, 1] {
+            heap.alloc(len).unwrap();
+        }
+        heap.alloc_tagged(20, "t").unwrap();
+        heap.reset().unwrap();
+        let fresh = self::heap(4096);
+        assert_eq!(heap.snapshot(), fresh.snapshot());
+        assert_eq!(heap.free_blocks().collect::<Vec<_>>(), fresh.free_blocks().collect::<Vec<_>>());
+        assert_eq!(heap.available(), 4096);
+        assert_eq!(heap.validate(), Ok(()));
+    }
+
+    #[test]
+    fn repeated_grow_keeps_contents() {
+        let mut heap = heap(4096);
+        let mut off = heap.alloc(8).unwrap();
+        // The block grows in place up to 1024 bytes, and then has to move
+        // past this one.
+        assert_eq!(heap.alloc(600), Ok(1024));
+        let mut len = 8;
+        heap.bytes_mut(off, len).copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut moves = 0;
+        while len < 1000 {
+            let new_len = len * 2 + 3;
+            let res = heap.realloc(off, len, new_len).unwrap();
+            if res != off {
+                moves += 1;
+            }
+            off = res;
+            assert!(heap.size_of(off) >= new_len);
+            assert!(heap.bytes(off, len).iter().enumerate().all(|(i, &b)| b == (i % 8 + 1) as u8), "{} bytes", len);
+            for i in len..new_len {
+                heap.bytes_mut(off, new_len)[i] = (i % 8 + 1) as u8;
+            }
+            len = new_len;
+            assert_eq!(heap.validate(), Ok(()));
+        }
+        assert_eq!(moves, 1);
+    }
+
+    #[test]
+    fn realloc_does_not_copy_the_red_zone() {
+        let mut heap = heap(1024);
+        heap.set_redzone(true);
+        let a = heap.alloc(10).unwrap();
+        heap.alloc(10).unwrap();
+        heap.bytes_mut(a, 10).fill(7);
+        let b = heap.realloc(a, 32, 100).unwrap();
+        assert_ne!(b, a);
+        assert_eq!(heap.bytes(b, 10), &[7; 10][..]);
+        assert!(heap.bytes(b, 100)[10..32].iter().all(|&x| x != REDZONE));
+        assert_eq!(heap.free_ptr(b), Ok(()));
+    }
+
+    #[test]
+    fn alloc_array_zeroes_and_checks_overflow() {
+        let mut heap = heap(1024);
+        let a = heap.alloc(256).unwrap();
+        heap.bytes_mut(a, 256).fill(0xAA);
+        heap.free_ptr(a).unwrap();
+        let b = heap.alloc_array(25, 8).unwrap();
+        assert_eq!(heap.size_of(b), 256);
+        assert!(heap.bytes(b, 200).iter().all(|&x| x == 0));
+        let available = heap.available();
+        assert_eq!(heap.alloc_array(usize::MAX, 2), Err(BuddyError::SizeOverflow));
+        assert_eq!(heap.alloc_array(2, usize::MAX / 2 + 1), Err(BuddyError::SizeOverflow));
+        assert_eq!(heap.available(), available);
+    }
+
+    #[test]
+    fn coalesce_all_merges_buddies_left_apart() {
+        let mut heap = heap(1024);
+        let blocks: Vec<pptr> = (0..4).map(|_| heap.alloc(64).unwrap()).collect();
+        heap.set_max_coalesce_order(6);
+        for off in blocks {
+            heap.free_ptr(off).unwrap();
+        }
+        assert_eq!(&heap.histogram()[6..10], &[4, 0, 1, 1]);
+        heap.set_max_coalesce_order(usize::MAX);
+        assert_eq!(heap.coalesce_all(), 5);
+        assert_eq!(&heap.histogram()[6..11], &[0, 0, 0, 0, 1]);
+        assert_eq!(heap.coalesce_all(), 0);
+        assert_eq!(heap.validate(), Ok(()));
+    }
+
+    #[test]
+    fn block_of_an_interior_pointer() {
+        let mut heap = heap(1024);
+        heap.alloc(64).unwrap();
+        let b = heap.alloc(200).unwrap();
+        assert_eq!(b, 256);
+        assert_eq!(heap.block_of(b), Some((b, 8)));
+        assert_eq!(heap.block_of(b + 100), Some((b, 8)));
+        assert_eq!(heap.block_of(b + 255), Some((b, 8)));
+        assert_eq!(heap.block_of(b + 256), None);
+        assert_eq!(heap.block_of(100), None);
+        assert_eq!(heap.block_of(5000), None);
+    }
+
+    #[test]
+    fn alloc_at_most_stops_at_the_minimum_order() {
+        let mut heap = BuddyAllocator::new();
+        heap.init_with_min_order(1024, 4);
+        for len in [512, 256, 128, 64, 32] {
+            heap.alloc(len).unwrap();
+        }
+        heap.alloc(16).unwrap();
+        assert_eq!(heap.alloc_at_most(300), Ok((1008, 16)));
+        assert_eq!(heap.alloc_at_most(300), Err(BuddyError::OutOfMemory));
+    }
+
+    #[test]
+    fn alloc_with_order_reports_the_usable_length() {
+        let mut heap = heap(1024);
+        let (off, usable) = heap.alloc_with_order(10).unwrap();
+        assert_eq!(usable, 16);
+        assert_eq!(usable, heap.size_of(off));
+        heap.bytes_mut(off, usable).fill(1);
+        let mut heap = self::heap(1024);
+        heap.set_redzone(true);
+        let (off, usable) = heap.alloc_with_order(10).unwrap();
+        assert_eq!(usable, 10);
+        assert_eq!(heap.size_of(off), 32);
+    }
+
+    #[test]
+    fn copy_between_and_within_allocations() {
+        let mut heap = heap(1024);
+        let a = heap.alloc(64).unwrap();
+        let b = heap.alloc(64).unwrap();
+        for (i, x) in heap.bytes_mut(a, 64).iter_mut().enumerate() {
+            *x = i as u8;
+        }
+        heap.copy(b, a, 64);
+        assert_eq!(heap.bytes(b, 64), heap.bytes(a, 64));
+        heap.copy(a + 1, a, 10);
+        assert_eq!(heap.bytes(a, 12), &[0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 11][..]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Copy is out of bounds")]
+    fn copy_past_the_end_panics() {
+        let mut heap = heap(1024);
+        heap.copy(1000, 0, 100);
+    }
+
+    #[test]
+    fn reserve_leaves_a_block_ready() {
+        let mut heap = heap(1024);
+        heap.reserve(5).unwrap();
+        // Both halves of the last split.
+        assert_eq!(heap.histogram()[5], 2);
+        let splits = heap.stats().splits;
+        let off = heap.alloc(32).unwrap();
+        assert_eq!(heap.size_of(off), 32);
+        assert_eq!(heap.stats().splits, splits);
+        assert_eq!(heap.reserve(5), Ok(()));
+        assert_eq!(heap.reserve(11), Err(BuddyError::OutOfMemory));
+        assert_eq!(heap.reserve(usize::MAX), Err(BuddyError::SizeOverflow));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn write_state_into_a_vec() {
+        let mut heap = BuddyAllocator::new();
+        heap.init_with_min_order(128, 5);
+        heap.alloc(20).unwrap();
+        let mut out = vec![];
+        heap.write_state(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out, "\n    32 [ 5] (32..63)\n    64 [ 6] (64..127)\n   128 [ 7] \nAvailable = 96 bytes\n");
+        let mut out = vec![];
+        BuddyAllocator::new().write_state(&mut out).unwrap();
+        assert_eq!(out, b"Memory is not initialized\n");
+    }
+
+
+    #[test]
+    fn alloc_succeeds_after_growing_a_full_heap() {
+        let mut heap = heap(1024);
+        heap.alloc(1024).unwrap();
+        assert_eq!(heap.alloc(16), Err(BuddyError::OutOfMemory));
+        heap.grow(1024).unwrap();
+        assert_eq!(heap.alloc(1024), Ok(1024));
+        assert_eq!(heap.available(), 0);
+        assert_eq!(heap.validate(), Ok(()));
+    }
+
+
+    #[test]
+    fn adjacent_blocks_that_are_not_buddies_stay_apart() {
+        let mut heap = heap(1024);
+        heap.grow(512).unwrap();
+        let offs: Vec<pptr> = (0..3).map(|_| heap.alloc(512).unwrap()).collect();
+        assert_eq!(offs, [1024, 0, 512]);
+        // 512 ends where 1024 starts, but its buddy is 0, and the buddy of
+        // 1024 would lie past the end of the memory.
+        heap.free_ptr(512).unwrap();
+        heap.free_ptr(1024).unwrap();
+        assert_eq!(heap.histogram()[9..11], [2, 0]);
+        assert_eq!(heap.largest_free_block(), 512);
+        heap.free_ptr(0).unwrap();
+        assert_eq!(heap.histogram()[9..11], [1, 1]);
+        assert_eq!(heap.available(), 1536);
+        assert_eq!(heap.validate(), Ok(()));
+    }
+
+
+    #[test]
+    fn report_leaks_lists_what_was_not_freed() {
+        let mut heap = heap(1024);
+        let a = heap.alloc(16).unwrap();
+        let b = heap.alloc(100).unwrap();
+        let c = heap.alloc(300).unwrap();
+        heap.free_ptr(b).unwrap();
+        assert_eq!(heap.report_leaks(), vec![(a, 4), (c, 9)]);
+    }
+
+
+    #[test]
+    fn blocks_are_aligned_to_their_size() {
+        let mut heap = heap(16384);
+        for len in [1, 16, 100, 512, 2000, 8192] {
+            let off = heap.alloc(len).unwrap();
+            let align = usize::min(heap.size_of(off), MAX_ALIGN);
+            assert!(heap.alignment(off) >= align, "{} bytes at {}", len, off);
+            assert_eq!(heap.bytes(off, len).as_ptr().align_offset(align), 0);
+        }
+    }
+
+
+    #[test]
+    fn address_ordered_reuses_the_lowest_block() {
+        let reuse = |ordered: bool| {
+            let mut heap = self::heap(1024);
+            heap.set_address_ordered(ordered);
+            let offs: Vec<pptr> = (0..8).map(|_| heap.alloc(64).unwrap()).collect();
+            for &off in &[offs[2], offs[0], offs[6], offs[4]] {
+                heap.free_ptr(off).unwrap();
+            }
+            (0..4).map(|_| heap.alloc(64).unwrap()).collect::<Vec<_>>()
+        };
+        assert_eq!(reuse(true), [0, 128, 256, 384]);
+        assert_eq!(reuse(false), [256, 384, 0, 128]);
+    }
+
+
+    #[test]
+    fn fresh_blocks_hold_the_fill_pattern() {
+        let mut heap = BuddyAllocator::new();
+        heap.init_with_fill(1024, 0xCD);
+        let a = heap.alloc(100).unwrap();
+        assert!(heap.bytes(a, 100).iter().all(|&b| b == 0xCD));
+        let b = heap.alloc_zeroed(100).unwrap();
+        assert!(heap.bytes(b, 100).iter().all(|&b| b == 0));
+    }
+
+
+    #[test]
+    fn orders_past_the_memory_are_out_of_memory() {
+        let mut heap = heap(1024);
+        let orders = heap.buddies.len();
+        for order in 11..orders {
+            assert_eq!(heap.alloc_exact_order(order), Err(BuddyError::OutOfMemory), "order {}", order);
+        }
+        assert_eq!(heap.alloc_exact_order(orders), Err(BuddyError::SizeOverflow));
+        assert_eq!(heap.find_free_memory(orders), None);
+        assert_eq!(heap.alloc(1 << 20), Err(BuddyError::OutOfMemory));
+        assert_eq!(heap.available(), 1024);
+        assert_eq!(heap.validate(), Ok(()));
+    }
+
+
+    #[test]
+    fn largest_free_block_counts_fast_bins() {
+        let mut heap = heap(1024);
+        heap.set_fast_bins(6, 4);
+        let offs: Vec<pptr> = (0..16).map(|_| heap.alloc(64).unwrap()).collect();
+        heap.free_ptr(offs[3]).unwrap();
+        assert_eq!(heap.binned().count(), 1);
+        assert_eq!(heap.largest_free_block(), 64);
+        assert!(heap.can_alloc(heap.largest_free_block()));
+        assert!(!heap.can_alloc(heap.largest_free_block() + 1));
+        assert_eq!(heap.fragmentation(), 0.0);
+        assert_eq!(heap.alloc(64), Ok(offs[3]));
+        assert_eq!(heap.largest_free_block(), 0);
+        assert!(!heap.can_alloc(1));
+    }
+}
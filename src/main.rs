@@ -1,179 +1,283 @@
-#![allow(dead_code)]
 #![allow(unused_must_use)]
 
-use std::cell::RefCell;
-use std::rc::{Rc,Weak};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
 
-#[allow(non_camel_case_types)]
-type pptr = usize;
+use rust_buddy::{pptr, BuddyAllocator, FileBackend, Policy};
 
-#[derive(Clone)]
-struct Buddy {
-    off: pptr,
-    next: Option<Rc<RefCell<Buddy>>>
+fn print_help() {
+    println!("Usage: rust-buddy [--file <path>] [--size <bytes>] [--init <bytes>] [--script <path>]");
+    println!();
+    println!("  --file <path>   Keep the memory in an image file at <path> (or --image)");
+    println!("  --size <bytes>  Size of a new image (default 1048576), or of the memory");
+    println!("  --init <bytes>  Initialize the memory with <bytes> on start");
+    println!("  --script <path> Run the commands in <path> instead of asking for them");
+    println!();
+    println!("Without --file, the memory lives in this process only and starts");
+    println!("with --init bytes, or --size bytes, or 1024 if neither is given.");
+    println!("Sizes take a K, M or G suffix, as in `--size 64M`.");
+    println!();
+    println!("A script has one command per line: `init <bytes>`, `alloc <bytes>`,");
+    println!("`free <name>`, `write <name> <text>`, `read <name>`, `save <path>`,");
+    println!("`load <path>`, `policy <smallest|topdown|firstfit|bestfit>` or `print`.");
+    println!("Blank lines and lines starting with `#` are skipped.");
 }
 
-struct BuddyAllocator {
-    buddies: [Option<Rc<RefCell<Buddy>>>; 32],
-    available: usize,
-    size: usize,
-    last: usize
+struct Args {
+    file: Option<String>,
+    size: Option<usize>,
+    init: Option<usize>,
+    script: Option<String>
 }
 
-const fn num_bits<T>() -> u32 { (std::mem::size_of::<T>() << 3) as u32 }
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+    let mut res = Args { file: None, size: None, init: None, script: None };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("`{}` needs a value", arg));
+        match &*arg {
+            "--file" | "--image" => res.file = Some(value()?),
+            "--size" => res.size = Some(parse_bytes(&value()?)?),
+            "--init" => res.init = Some(parse_bytes(&value()?)?),
+            "--script" => res.script = Some(value()?),
+            _ => return Err(format!("Unknown argument `{}`", arg))
+        }
+    }
+    Ok(res)
+}
 
-#[inline]
-fn get_idx(x: usize) -> usize {
-    assert!(x > 0);
-    (num_bits::<usize>() - (x-1).leading_zeros()) as usize
+/// Reads a number of bytes, optionally followed by `K`, `M` or `G` for
+/// KiB, MiB or GiB.
+fn parse_bytes(s: &str) -> Result<usize, String> {
+    let (digits, shift) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 10),
+        Some('M') => (&s[..s.len() - 1], 20),
+        Some('G') => (&s[..s.len() - 1], 30),
+        _ => (s, 0)
+    };
+    match digits.parse::<usize>().ok().and_then(|n| n.checked_mul(1 << shift)) {
+        None | Some(0) => Err(format!("Expected a positive number of bytes, got `{}`", s)),
+        Some(n) => Ok(n)
+    }
 }
 
-impl BuddyAllocator {
-    pub fn new() -> Self {
-        BuddyAllocator {
-            buddies: Default::default(),
-            available: 0,
-            size: 0,
-            last: 0
-        }
+enum Command {
+    Init(usize),
+    Alloc(usize),
+    Free(String),
+    Write(String, String),
+    Read(String),
+    Save(String),
+    Load(String),
+    Policy(Policy),
+    Print
+}
+
+fn parse_policy(name: &str) -> Result<Policy, String> {
+    match name {
+        "smallest" => Ok(Policy::SmallestFit),
+        "topdown" => Ok(Policy::TopDown),
+        "firstfit" => Ok(Policy::FirstFit),
+        "bestfit" => Ok(Policy::BestFit),
+        _ => Err(format!("Expected `smallest`, `topdown`, `firstfit` or `bestfit`, got `{}`", name))
     }
-    pub fn init(&mut self, size: usize) {
-        let mut idx = get_idx(size);
-        if 1 << idx > size {
-            idx -= 1;
-        }
-        self.last = usize::min(idx + 1, 31);
-        self.available = 1 << idx;
-        self.size = self.available;
-        self.buddies[idx] = Some(Rc::new(RefCell::new(Buddy{
-            off: 0,
-            next: None
-        })));
-        println!("Memory is initiated with {} bytes", self.size);
-    }
-    fn apply(&mut self, to_add: &mut Vec<(usize, pptr)>) {
-        for b in to_add {
-            let n = if let Some(d) = &self.buddies[b.0] {
-                Buddy {
-                    off: b.1,
-                    next: Some(d.clone())
-                }
-            } else {
-                Buddy{
-                    off: b.1,
-                    next: None
-                }
-            };
-            self.buddies[b.0] = Some(Rc::new(RefCell::new(n)));
-        }
+}
+
+/// Reads one line of a script. Blank lines and `#` comments give `None`.
+fn parse_command(line: &str) -> Result<Option<Command>, String> {
+    let mut words = line.split_whitespace();
+    let cmd = match words.next() {
+        None => return Ok(None),
+        Some(cmd) if cmd.starts_with('#') => return Ok(None),
+        Some(cmd) => cmd
+    };
+    if cmd == "write" {
+        let rest = line.trim_start()[cmd.len()..].trim_start();
+        return match rest.split_once(char::is_whitespace) {
+            Some((name, text)) => Ok(Some(Command::Write(name.to_string(), text.trim_start().to_string()))),
+            None => Err("`write` needs a variable name and a text".to_string())
+        };
     }
-    fn find_free_memory(&mut self, idx: usize, 
-        to_add: &mut Vec<(usize, pptr)>, 
-        lend: bool) 
-    -> Option<pptr> {
-        if idx == 32 {
-            None
-        } else {
-            let res;
-            if let Some(b) = self.buddies[idx].clone() {
-                self.buddies[idx] = b.borrow().next.clone();
-                res = b.borrow().off;
-            } else {
-                res = self.find_free_memory(idx+1, to_add, true)?;
-            }
-            if idx > 0 && lend {
-                to_add.push((idx-1, res + (1 << (idx-1))));
+    let arg = words.next();
+    if words.next().is_some() {
+        return Err(format!("Too many arguments to `{}`", cmd));
+    }
+    let len = || {
+        let arg = arg.ok_or(format!("`{}` needs a length", cmd))?;
+        arg.parse().map_err(|_| format!("Expected an integer, got `{}`", arg))
+    };
+    match (cmd, arg) {
+        ("init", _) => Ok(Some(Command::Init(len()?))),
+        ("alloc", _) => Ok(Some(Command::Alloc(len()?))),
+        ("free", Some(name)) => Ok(Some(Command::Free(name.to_string()))),
+        ("free", None) => Err("`free` needs a variable name".to_string()),
+        ("read", Some(name)) => Ok(Some(Command::Read(name.to_string()))),
+        ("read", None) => Err("`read` needs a variable name".to_string()),
+        ("save", Some(path)) => Ok(Some(Command::Save(path.to_string()))),
+        ("save", None) => Err("`save` needs a path".to_string()),
+        ("load", Some(path)) => Ok(Some(Command::Load(path.to_string()))),
+        ("load", None) => Err("`load` needs a path".to_string()),
+        ("policy", Some(name)) => Ok(Some(Command::Policy(parse_policy(name)?))),
+        ("policy", None) => Err("`policy` needs `smallest`, `topdown`, `firstfit` or `bestfit`".to_string()),
+        ("print", None) => Ok(Some(Command::Print)),
+        _ => Err(format!("Unknown command `{}`", line.trim()))
+    }
+}
+
+/// The allocator being driven together with the variables allocated in it.
+struct Session<'a> {
+    heap: &'a mut BuddyAllocator,
+    vars: BTreeMap<String, (pptr, usize)>,
+    id: usize
+}
+
+impl<'a> Session<'a> {
+    /// Picks up the variables of an earlier run from the tags of the live
+    /// allocations, so a reopened image keeps its names. Their lengths come
+    /// back as the usable size of each block.
+    fn new(heap: &'a mut BuddyAllocator) -> Self {
+        let mut vars = BTreeMap::new();
+        let mut id = 0;
+        heap.walk_allocated(|v, len, tag| {
+            if let Some(name) = tag {
+                if let Some(n) = name.strip_prefix('v').and_then(|n| n.parse().ok()) {
+                    id = usize::max(id, n);
+                }
+                vars.insert(name.to_string(), (v, len));
             }
-            Some(res)
-        }
+        });
+        Session { heap, vars, id }
     }
-    pub fn alloc(&mut self, len: usize) -> Result<pptr, &str> {
-        let mut to_add = vec!();
-        let idx = get_idx(len);
-        match self.find_free_memory(idx, &mut to_add, false) {
-            Some(res) => {
-                if res >= self.size {
-                    Err("Out of memory")
+    fn run(&mut self, cmd: Command) {
+        match cmd {
+            Command::Alloc(len) => {
+                let name = format!("v{}", self.id + 1);
+                match self.heap.alloc_tagged(len, &name) {
+                    Ok(v) => {
+                        self.id += 1;
+                        self.vars.insert(name.clone(), (v, len));
+                        println!("`{}` is allocated at address {}", name, v);
+                    }
+                    Err(e) => println!("{}", e)
+                }
+            }
+            Command::Free(name) => {
+                if let Some(v) = self.vars.remove(&name) {
+                    match self.heap.free_ptr(v.0) {
+                        Ok(()) => println!("`{}` is deleted from memory", name),
+                        Err(e) => println!("{}", e)
+                    }
                 } else {
-                    self.apply(&mut to_add);
-                    self.available -= 1 << idx;
-                    Ok(res)
+                    println!("No such variable `{}`", name);
                 }
             }
-            None => Err("Out of memory")
-        }
-    }
-    pub fn free(&mut self, off: pptr, len: usize) {
-        let idx = get_idx(len);
-        let len = 1 << idx;
-        let end = off + len;
-        if idx+1 <= self.last {
-            let mut curr = self.buddies[idx].clone();
-            let mut prev: Weak<RefCell<Buddy>> = Weak::new();
-            while let Some(b) = curr {
-                let e = b.borrow();
-                let is_left_buddy = off & (1 << idx) == 0;
-                if (e.off == end && is_left_buddy) || (e.off + len == off && !is_left_buddy)  {
-                    let off = pptr::min(off,e.off);
-                    if let Some(p) = prev.upgrade() {
-                        p.borrow_mut().next = e.next.clone();
-                    } else {
-                        self.buddies[idx] = e.next.clone();
+            Command::Write(name, text) => match self.vars.get(&name) {
+                Some(&(v, len)) => {
+                    let text = text.as_bytes();
+                    let n = usize::min(text.len(), len);
+                    let bytes = self.heap.bytes_mut(v, len);
+                    bytes[..n].copy_from_slice(&text[..n]);
+                    bytes[n..].fill(0);
+                    if n < text.len() {
+                        println!("Only the first {} bytes fit in `{}`", n, name);
                     }
-                    self.available -= len;
-                    self.free(off, len << 1);
-                    return;
                 }
-                prev = Rc::downgrade(&b);
-                curr = e.next.clone();
+                None => println!("No such variable `{}`", name)
+            },
+            Command::Read(name) => match self.vars.get(&name) {
+                Some(&(v, len)) => {
+                    let bytes = self.heap.bytes(v, len);
+                    let end = bytes.iter().position(|&b| b == 0).unwrap_or(len);
+                    println!("`{}` holds \"{}\"", name, String::from_utf8_lossy(&bytes[..end]));
+                }
+                None => println!("No such variable `{}`", name)
+            },
+            Command::Save(path) => match self.save(&path) {
+                Ok(()) => println!("Memory and variables are saved to `{}`", path),
+                Err(e) => println!("Cannot save to `{}`: {}", path, e)
+            },
+            Command::Load(path) => match self.load(&path) {
+                Ok(()) => println!("Memory and variables are loaded from `{}`", path),
+                Err(e) => println!("Cannot load `{}`: {}", path, e)
+            },
+            Command::Policy(policy) => {
+                self.heap.set_policy(policy);
+                println!("Blocks are now split by {:?}", policy);
             }
-        }
-        self.available += len;
-        let n = if let Some(d) = &self.buddies[idx] {
-            Buddy {
-                off,
-                next: Some(d.clone())
+            Command::Print => {
+                self.heap.print();
+                if !self.vars.is_empty() {
+                    println!("Variables:");
+                    for (n, v) in &self.vars {
+                        println!("{:>8}: {:>4}..{:<4} ({} bytes)", n, v.0, v.0+v.1-1, v.1);
+                    }
+                }
             }
-        } else {
-            Buddy{
-                off,
-                next: None
+            Command::Init(len) => {
+                self.heap.init(len);
+                self.vars.clear();
+                println!("Memory is initiated with {} bytes", self.heap.capacity());
             }
-        };
-        self.buddies[idx] = Some(Rc::new(RefCell::new(n)));
-    }
-    pub fn print(&self) {
-        println!();
-        for idx in 0..self.last {
-            print!("{:>6} [{:>2}] ", 1 << idx, idx);
-            let mut curr = self.buddies[idx].clone();
-            while let Some(b) = curr {
-                let b = b.borrow();
-                print!("({}..{})", b.off, b.off + (1 << idx) - 1);
-                curr = if let Some(nxt) = b.next.clone() {
-                    Some(nxt)
-                } else {
-                    None
-                };
+        }
+    }
+    /// Writes the allocator image followed by one `name offset length` line
+    /// per variable.
+    fn save(&self, path: &str) -> std::io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.heap.save_image(&mut w)?;
+        for (name, &(v, len)) in &self.vars {
+            writeln!(w, "{} {} {}", name, v, len)?;
+        }
+        w.flush()
+    }
+    /// Reads back what `save` wrote. The saved memory must be as large as
+    /// the current one, so a scenario is replayed on the heap it was made
+    /// for; nothing changes if it is not.
+    fn load(&mut self, path: &str) -> Result<(), String> {
+        let mut r = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+        let heap = BuddyAllocator::load_image(&mut r).ok_or("the file does not hold a valid image")?;
+        if heap.capacity() != self.heap.capacity() {
+            return Err(format!(
+                "it holds {} bytes of memory, but this one has {}; `init` it to that size first",
+                heap.capacity(),
+                self.heap.capacity()
+            ));
+        }
+        let mut names = String::new();
+        r.read_to_string(&mut names).map_err(|e| e.to_string())?;
+        let mut vars = BTreeMap::new();
+        let mut id = 0;
+        for line in names.lines() {
+            let var = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [name, v, len] => v.parse().ok().zip(len.parse().ok()).map(|var| (name, var)),
+                _ => None
+            };
+            let (name, (v, len)) = var.ok_or(format!("Bad variable line `{}`", line))?;
+            if let Some(n) = name.strip_prefix('v').and_then(|n| n.parse().ok()) {
+                id = usize::max(id, n);
             }
-            println!();
+            vars.insert(name.to_string(), (v, len));
         }
-        println!("Available = {} bytes", self.available);
+        *self.heap = heap;
+        self.vars = vars;
+        self.id = id;
+        Ok(())
     }
 }
 
-fn print_help() {
-    println!("Usage: ");
-}
-
 fn input(print_options: bool, msg: &str) -> Option<String> {
     use std::io::{stdin,stdout,Write};
     let mut s=String::new();
     if print_options {
         println!("\nOptions:");
-        println!("  i - Init memory");
+        println!("  i - Init a fresh memory, dropping every variable");
         println!("  a - Allocate new variable given a length");
         println!("  f - Free a variable given its name");
+        println!("  w - Write a text into a variable");
+        println!("  r - Read the text in a variable");
+        println!("  s - Save the memory and variables to a file");
+        println!("  l - Load the memory and variables from a file");
+        println!("  o - Choose which free block is split (smallest, topdown, firstfit or bestfit)");
         println!("  p - Print info");
         println!("  q - Quit");
     }
@@ -194,47 +298,84 @@ fn input(print_options: bool, msg: &str) -> Option<String> {
 }
 
 fn main() {
-    use std::collections::HashMap;
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            print_help();
+            std::process::exit(2);
+        }
+    };
+    let mut backend;
+    let mut heap;
+    let a = if let Some(path) = &args.file {
+        backend = match FileBackend::open(path, args.size.unwrap_or(1024 * 1024)) {
+            Ok(backend) => backend,
+            Err(e) => {
+                eprintln!("Cannot open `{}`: {}", path, e);
+                std::process::exit(1);
+            }
+        };
+        let a = backend.heap_mut();
+        if let Some(len) = args.init {
+            a.init(len);
+        }
+        a
+    } else {
+        heap = BuddyAllocator::new();
+        heap.init(args.init.or(args.size).unwrap_or(1024));
+        &mut heap
+    };
+    println!("Memory is initiated with {} bytes", a.capacity());
+    let mut session = Session::new(a);
+    if !session.vars.is_empty() {
+        println!("Variables {} are kept from the last run", session.vars.keys().cloned().collect::<Vec<_>>().join(", "));
+    }
 
-    let mut id = 0;
-    let mut a = BuddyAllocator::new();
-    let mut map: HashMap<String, (pptr, usize)> = HashMap::new();
-    
-    a.init(1024);
+    if let Some(path) = &args.script {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("Cannot open `{}`: {}", path, e);
+                std::process::exit(1);
+            }
+        };
+        for (n, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.expect("Cannot read the script");
+            match parse_command(&line) {
+                Ok(Some(cmd)) => {
+                    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| session.run(cmd)));
+                }
+                Ok(None) => {}
+                Err(e) => println!("{}:{}: {}", path, n + 1, e)
+            }
+        }
+        return;
+    }
     while let Some(cmd) = input(true, "Your choice: ") {
         std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-            if let "a" = &*cmd {
-                let len = input(false, "Length: ").expect("Wrong input");
-                let len: usize = len.parse().expect("Expected an integer");
-                assert!(len > 0);
-                id += 1;
-                let v = a.alloc(len).expect("Out of memory");
-                let name = format!("v{}", id);
-                map.insert(name.clone(), (v, len));
-                println!("`{}` is allocated at address {}", name, v);
-            } else if let "f" = &*cmd {
-                let name = input(false, "Variable ident: ").expect("Wrong input");
-                if let Some(v) = map.remove(&name) {
-                    a.free(v.0, v.1);
-                    println!("`{}` is deleted from memory", name);
-                } else {
-                    println!("No such variable `{}`", name);
+            let cmd = match &*cmd {
+                "a" => {
+                    let len = input(false, "Length: ").expect("Wrong input");
+                    Command::Alloc(len.parse().expect("Expected an integer"))
                 }
-            } else if let "p" = &*cmd {
-                a.print();
-                if !map.is_empty() {
-                    println!("Variables:");
-                    for (n, v) in &map {
-                        println!("{:>8}: {:>4}..{:<4} ({} bytes)", n, v.0, v.0+v.1-1, v.1);
-                    }
+                "f" => Command::Free(input(false, "Variable ident: ").expect("Wrong input")),
+                "w" => {
+                    let name = input(false, "Variable ident: ").expect("Wrong input");
+                    Command::Write(name, input(false, "Text: ").expect("Wrong input"))
                 }
-            } else if let "i" = &*cmd {
-                let len = input(false, "Size: ").expect("Wrong input");
-                let len: usize = len.parse().expect("Expected an integer");
-                a = BuddyAllocator::new();
-                a.init(len);
-                map.clear();
-            }
+                "r" => Command::Read(input(false, "Variable ident: ").expect("Wrong input")),
+                "s" => Command::Save(input(false, "File: ").expect("Wrong input")),
+                "l" => Command::Load(input(false, "File: ").expect("Wrong input")),
+                "o" => Command::Policy(parse_policy(&input(false, "Policy: ").expect("Wrong input")).expect("Unknown policy")),
+                "p" => Command::Print,
+                "i" => {
+                    let len = input(false, "Size: ").expect("Wrong input");
+                    Command::Init(len.parse().expect("Expected an integer"))
+                }
+                _ => return
+            };
+            session.run(cmd);
         }));
     }
 }
@@ -0,0 +1,83 @@
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+use core::slice;
+
+use crate::Backing;
+
+/// Largest alignment the start of the memory is given.
+pub const MAX_ALIGN: usize = 4096;
+
+/// The zero-initialized bytes an allocator owns unless `init_with` gives it
+/// another `Backing`. The start is aligned to the size of the memory (up to
+/// `MAX_ALIGN`), so a block whose offset is a multiple of its size has an
+/// address that is aligned the same way.
+pub(crate) struct Memory {
+    ptr: NonNull<u8>,
+    len: usize
+}
+
+// SAFETY: `Memory` uniquely owns its allocation, like a `Box<[u8]>`.
+unsafe impl Send for Memory {}
+unsafe impl Sync for Memory {}
+
+impl Memory {
+    pub fn new(len: usize) -> Self {
+        let layout = Self::layout(len).expect("Memory is too large");
+        Self::try_new(len).unwrap_or_else(|| handle_alloc_error(layout))
+    }
+    /// Like `new`, but returns `None` rather than aborting when `len` bytes
+    /// cannot be had.
+    pub fn try_new(len: usize) -> Option<Self> {
+        if len == 0 {
+            return Some(Memory { ptr: NonNull::dangling(), len });
+        }
+        let layout = Self::layout(len)?;
+        // SAFETY: the layout has a non-zero size.
+        let ptr = unsafe { alloc_zeroed(layout) };
+        NonNull::new(ptr).map(|ptr| Memory { ptr, len })
+    }
+    fn layout(len: usize) -> Option<Layout> {
+        let align = if len >= MAX_ALIGN { MAX_ALIGN } else { len.next_power_of_two() };
+        Layout::from_size_align(len, align).ok()
+    }
+}
+
+impl Backing for Memory {
+    /// Moves the bytes to a bigger allocation.
+    fn grow_to(&mut self, len: usize) -> bool {
+        if len > self.len {
+            let mut memory = match Memory::try_new(len) {
+                Some(memory) => memory,
+                None => return false
+            };
+            memory[..self.len].copy_from_slice(self);
+            *self = memory;
+        }
+        true
+    }
+}
+
+impl Deref for Memory {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `ptr` points to `len` initialized bytes owned by `self`.
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for Memory {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as in `deref`, and `&mut self` makes the access unique.
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // SAFETY: allocated in `new` with this same layout.
+            unsafe { dealloc(self.ptr.as_ptr(), Self::layout(self.len).unwrap()) }
+        }
+    }
+}
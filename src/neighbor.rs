@@ -0,0 +1,61 @@
+use crate::{pptr, BuddyAllocator};
+
+/// A block next to another one, as reported by `neighbors`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Neighbor {
+    pub offset: pptr,
+    /// Whether a free block of the same order starts at `offset`, which is
+    /// what `free` needs to merge with it.
+    pub free: bool,
+    /// Whether this is the buddy, the one neighbor a block can merge with.
+    pub buddy: bool
+}
+
+impl BuddyAllocator {
+    /// The blocks of order `order` right before and after the one at
+    /// `off`, or `None` for a side that lies outside the memory. One of the
+    /// two is the buddy, at `off ^ (1 << order)`; the other can only merge
+    /// with `off` once both have merged into larger blocks. Both are `None`
+    /// if `order` is above the largest order.
+    pub fn neighbors(&self, off: pptr, order: usize) -> (Option<Neighbor>, Option<Neighbor>) {
+        if order >= self.buddies.len() {
+            return (None, None);
+        }
+        let len = 1 << order;
+        let neighbor = |offset: pptr| Neighbor {
+            offset,
+            free: matches!(self.free.get(&offset), Some(&(i, _)) if i == order)
+                || self.fast_bins.get(order).is_some_and(|bin| bin.contains(&offset)),
+            buddy: offset == off ^ len
+        };
+        let left = off.checked_sub(len).map(neighbor);
+        let right = off.checked_add(len).filter(|&o| o < self.size && self.size - o >= len).map(neighbor);
+        (left, right)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neighbors_of_a_block() {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        let a = heap.alloc(128).unwrap();
+        let b = heap.alloc(128).unwrap();
+        heap.free_ptr(a).unwrap();
+        let (left, right) = heap.neighbors(b, 7);
+        assert_eq!(left, Some(Neighbor { offset: 0, free: true, buddy: true }));
+        assert_eq!(right, Some(Neighbor { offset: 256, free: false, buddy: false }));
+        assert_eq!(heap.neighbors(0, 10), (None, None));
+    }
+
+    #[test]
+    fn neighbors_past_the_largest_order() {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        assert_eq!(heap.neighbors(0, 64), (None, None));
+        assert_eq!(heap.neighbors(0, usize::MAX), (None, None));
+    }
+}
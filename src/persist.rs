@@ -0,0 +1,180 @@
+use std::io::{self, Read, Write};
+
+use crate::{num_bits, BuddyAllocator, HeapSnapshot, MAX_TAG_LEN};
+
+const MAGIC: [u8; 8] = *b"RSBUDDY\0";
+const VERSION: u64 = 5;
+
+fn write_word<W: Write>(w: &mut W, x: usize) -> io::Result<()> {
+    w.write_all(&(x as u64).to_le_bytes())
+}
+
+fn read_word<R: Read>(r: &mut R) -> Option<usize> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf).ok()?;
+    Some(u64::from_le_bytes(buf) as usize)
+}
+
+/// Passes bytes through while hashing them with 64-bit FNV-1a, so a torn
+/// or corrupted image shows up as a checksum mismatch on load.
+struct Checksum<T> {
+    inner: T,
+    hash: u64
+}
+
+impl<T> Checksum<T> {
+    fn new(inner: T) -> Self {
+        Checksum { inner, hash: 0xcbf29ce484222325 }
+    }
+    fn update(&mut self, buf: &[u8]) {
+        for &b in buf {
+            self.hash = (self.hash ^ b as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+impl<W: Write> Write for Checksum<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.update(&buf[..n]);
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Read for Checksum<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl BuddyAllocator {
+    /// Writes the free-lists, the allocation map and the accounting behind
+    /// a magic number and format version, followed by a checksum of all of
+    /// it. The arena bytes are not included.
+    pub fn save_metadata<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut c = Checksum::new(&mut *w);
+        self.write_metadata(&mut c)?;
+        let hash = c.hash;
+        w.write_all(&hash.to_le_bytes())
+    }
+    fn write_metadata<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let snapshot = self.snapshot();
+        w.write_all(&MAGIC)?;
+        write_word(w, VERSION as usize)?;
+        write_word(w, snapshot.size)?;
+        write_word(w, snapshot.available)?;
+        write_word(w, snapshot.last)?;
+        write_word(w, snapshot.free_lists.len())?;
+        write_word(w, snapshot.min_order)?;
+        write_word(w, snapshot.free_lists.iter().map(Vec::len).sum())?;
+        for (idx, list) in snapshot.free_lists.iter().enumerate() {
+            for &off in list {
+                write_word(w, idx)?;
+                write_word(w, off)?;
+            }
+        }
+        write_word(w, snapshot.allocated.len())?;
+        for &(off, idx) in &snapshot.allocated {
+            write_word(w, off)?;
+            write_word(w, idx)?;
+        }
+        write_word(w, snapshot.tags.len())?;
+        for (off, tag) in &snapshot.tags {
+            write_word(w, *off)?;
+            write_word(w, tag.len())?;
+            w.write_all(tag.as_bytes())?;
+        }
+        Ok(())
+    }
+    /// Rebuilds an allocator written by `save_metadata`, with a zeroed arena
+    /// of the saved size. Returns `None` if the magic number, version or
+    /// checksum does not match or the saved state does not pass `validate`,
+    /// in which case the caller should `init` a fresh one.
+    pub fn load_metadata<R: Read>(r: &mut R) -> Option<BuddyAllocator> {
+        let mut c = Checksum::new(&mut *r);
+        let snapshot = Self::read_metadata(&mut c);
+        let hash = c.hash;
+        let mut buf = [0; 8];
+        r.read_exact(&mut buf).ok()?;
+        if u64::from_le_bytes(buf) != hash {
+            return None;
+        }
+        BuddyAllocator::restore(&snapshot?)
+    }
+    /// Like `save_metadata`, followed by the bytes of the memory, which is
+    /// what a `FileBackend` keeps in its file.
+    pub fn save_image<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.save_metadata(w)?;
+        w.write_all(&self.memory)
+    }
+    /// Reads back what `save_image` wrote, or `None` like `load_metadata`,
+    /// or if the memory is cut short.
+    pub fn load_image<R: Read>(r: &mut R) -> Option<BuddyAllocator> {
+        let mut heap = Self::load_metadata(r)?;
+        r.read_exact(&mut heap.memory).ok()?;
+        Some(heap)
+    }
+    fn read_metadata<R: Read>(r: &mut R) -> Option<HeapSnapshot> {
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic).ok()?;
+        if magic != MAGIC || read_word(r)? != VERSION as usize {
+            return None;
+        }
+        let size = read_word(r)?;
+        let available = read_word(r)?;
+        let last = read_word(r)?;
+        let orders = read_word(r)?;
+        let min_order = read_word(r)?;
+        if orders > num_bits::<usize>() as usize {
+            return None;
+        }
+        let mut free_lists = vec![vec![]; orders];
+        for _ in 0..read_word(r)? {
+            let idx = read_word(r)?;
+            free_lists.get_mut(idx)?.push(read_word(r)?);
+        }
+        let mut allocated = vec!();
+        for _ in 0..read_word(r)? {
+            let off = read_word(r)?;
+            allocated.push((off, read_word(r)?));
+        }
+        let mut tags = vec!();
+        for _ in 0..read_word(r)? {
+            let off = read_word(r)?;
+            let len = read_word(r)?;
+            if len > MAX_TAG_LEN {
+                return None;
+            }
+            let mut buf = vec![0; len];
+            r.read_exact(&mut buf).ok()?;
+            tags.push((off, String::from_utf8(buf).ok()?));
+        }
+        Some(HeapSnapshot { size, available, last, min_order, free_lists, allocated, tags })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::BuddyAllocator;
+
+    #[test]
+    fn flipped_metadata_byte_is_caught() {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        heap.alloc_tagged(100, "a").unwrap();
+        heap.alloc(30).unwrap();
+        let mut image = vec![];
+        heap.save_image(&mut image).unwrap();
+        assert_eq!(BuddyAllocator::load_image(&mut &image[..]).map(|h| h.snapshot()), Some(heap.snapshot()));
+        for i in 0..image.len() - 1024 {
+            let mut bad = image.clone();
+            bad[i] ^= 0x10;
+            assert!(BuddyAllocator::load_image(&mut &bad[..]).is_none(), "flip at byte {}", i);
+        }
+    }
+}
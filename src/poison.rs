@@ -0,0 +1,84 @@
+use alloc::vec::Vec;
+
+use crate::{pptr, BuddyAllocator, BuddyError};
+
+/// Byte written over free memory while poisoning is on.
+pub const POISON: u8 = 0xDE;
+
+impl BuddyAllocator {
+    /// Turns use-after-free detection on or off. While on, all free memory
+    /// holds `POISON`: freed blocks are filled with it, and a block about
+    /// to be handed out or checked by `validate` must still hold it.
+    pub fn set_poison(&mut self, on: bool) {
+        if on && !self.poison {
+            let mut free: Vec<_> = self.free_blocks().map(|(_, off, len)| (off, len)).collect();
+            free.extend(self.binned().map(|(idx, off)| (off, 1 << idx)));
+            for (off, len) in free {
+                self.memory[off..off + len].fill(POISON);
+            }
+        }
+        self.poison = on;
+    }
+    /// Makes sure the free block at `off`, about to be handed out or split
+    /// from as a block of order `idx`, was not written to after it was
+    /// freed. The block is re-poisoned when it was, so the error is reported
+    /// only once.
+    pub(crate) fn check_block_poison(&mut self, off: pptr, idx: usize) -> Result<(), BuddyError> {
+        let block = &mut self.memory[off..off + (1 << idx)];
+        match block.iter().position(|&b| b != POISON) {
+            Some(pos) => {
+                block.fill(POISON);
+                Err(BuddyError::WriteAfterFree { offset: off + pos })
+            }
+            None => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BuddyAllocator, BuddyError, CorruptionError, Policy};
+
+    #[test]
+    fn poison_is_checked_on_the_block_the_policy_splits() {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        heap.set_poison(true);
+        heap.set_policy(Policy::TopDown);
+        heap.alloc(64).unwrap();
+        heap.memory[600] = 1;
+        assert_eq!(heap.alloc(32), Err(BuddyError::WriteAfterFree { offset: 600 }));
+        assert_eq!(heap.alloc(32), Ok(512));
+        assert_eq!(heap.validate(), Ok(()));
+    }
+
+    #[test]
+    fn write_after_free_is_caught() {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        heap.set_poison(true);
+        let a = heap.alloc(100).unwrap();
+        heap.alloc(100).unwrap();
+        heap.bytes_mut(a, 100).fill(1);
+        heap.free_ptr(a).unwrap();
+        assert_eq!(heap.validate(), Ok(()));
+        // A stray write through a dangling offset. `bytes_mut` itself
+        // refuses free blocks in debug builds, so go around it.
+        heap.memory[a + 10] = 1;
+        assert_eq!(heap.validate(), Err(CorruptionError::WriteAfterFree { offset: a + 10, order: 7 }));
+        assert_eq!(heap.alloc(100), Err(BuddyError::WriteAfterFree { offset: a + 10 }));
+        assert_eq!(heap.validate(), Ok(()));
+        assert_eq!(heap.alloc(100), Ok(a));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Bytes are not inside one allocation")]
+    fn bytes_mut_refuses_a_freed_block() {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        let a = heap.alloc(100).unwrap();
+        heap.free_ptr(a).unwrap();
+        heap.bytes_mut(a, 1)[0] = 1;
+    }
+}
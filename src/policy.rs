@@ -0,0 +1,82 @@
+use crate::BuddyAllocator;
+
+/// Which free block is split when there is none of the order asked for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// Split the smallest larger block, keeping big blocks whole for as
+    /// long as possible.
+    #[default]
+    SmallestFit,
+    /// Split the largest free block, so the small orders are only fed from
+    /// one place and their lists stay short.
+    TopDown,
+    /// Take the free block at the lowest offset that is large enough,
+    /// splitting it if it is larger, even when a block of the exact order
+    /// sits further up.
+    FirstFit,
+    /// Like `SmallestFit`, but of the blocks of the order it takes from,
+    /// pick the one at the lowest offset rather than the most recently
+    /// freed.
+    BestFit
+}
+
+impl BuddyAllocator {
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = policy;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{pptr, BuddyAllocator, Policy};
+
+    /// Offsets of the blocks handed out for `lens`, in order, and the free
+    /// blocks of each order after them.
+    fn layout(policy: Policy, lens: &[usize]) -> (Vec<pptr>, Vec<usize>) {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        heap.set_policy(policy);
+        let offs = lens.iter().map(|&len| heap.alloc(len).unwrap()).collect();
+        (offs, heap.histogram())
+    }
+
+    /// Offset `alloc(64)` gets from `policy` once `lens` are allocated and
+    /// the ones at the indices in `frees` are freed again.
+    fn pick(policy: Policy, lens: &[usize], frees: &[usize]) -> pptr {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        let offs: Vec<pptr> = lens.iter().map(|&len| heap.alloc(len).unwrap()).collect();
+        for &i in frees {
+            heap.free_ptr(offs[i]).unwrap();
+        }
+        heap.set_policy(policy);
+        heap.alloc(64).unwrap()
+    }
+
+    #[test]
+    fn first_fit_and_best_fit_pick_different_blocks() {
+        // A free 256-byte block at 0 and a free 64-byte one at 320.
+        let lens = [256, 64, 64, 128, 512];
+        assert_eq!(pick(Policy::FirstFit, &lens, &[0, 2]), 0);
+        assert_eq!(pick(Policy::BestFit, &lens, &[0, 2]), 320);
+        assert_eq!(pick(Policy::SmallestFit, &lens, &[0, 2]), 320);
+        // Free 64-byte blocks at 64 and, freed last, at 320.
+        let lens = [64, 64, 128, 64, 64, 128, 512];
+        assert_eq!(pick(Policy::BestFit, &lens, &[1, 4]), 64);
+        assert_eq!(pick(Policy::SmallestFit, &lens, &[1, 4]), 320);
+    }
+
+    #[test]
+    fn smallest_fit_and_top_down_split_different_blocks() {
+        let lens = [64, 32, 128];
+        let (offs, free) = layout(Policy::SmallestFit, &lens);
+        assert_eq!(offs, vec![0, 64, 128]);
+        assert_eq!(&free[5..10], &[1, 0, 0, 1, 1]);
+        let (offs, free) = layout(Policy::TopDown, &lens);
+        assert_eq!(offs, vec![0, 512, 640]);
+        assert_eq!(&free[5..10], &[1, 2, 1, 2, 0]);
+    }
+}
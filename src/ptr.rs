@@ -0,0 +1,139 @@
+use core::marker::PhantomData;
+use core::mem;
+use core::slice;
+
+use crate::memory::MAX_ALIGN;
+use crate::{pptr, BuddyAllocator, BuddyError};
+
+/// Types for which every byte pattern is a valid value, so they can be
+/// read straight out of the memory whatever was written there before.
+///
+/// # Safety
+/// Implementors must have no padding, no invalid bit patterns and no drop
+/// glue.
+pub unsafe trait Plain: Copy {}
+
+macro_rules! plain {
+    ($($t:ty),*) => { $(unsafe impl Plain for $t {})* }
+}
+
+plain!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: Plain, const N: usize> Plain for [T; N] {}
+
+/// An offset into the memory that remembers the type stored there.
+pub struct Ptr<T> {
+    off: pptr,
+    _marker: PhantomData<T>
+}
+
+impl<T> Clone for Ptr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Ptr<T> {}
+
+impl<T> Ptr<T> {
+    pub fn offset(&self) -> pptr {
+        self.off
+    }
+}
+
+impl BuddyAllocator {
+    /// Allocates a zeroed block that fits a `T` at its natural alignment.
+    pub fn alloc_obj<T: Plain>(&mut self) -> Result<Ptr<T>, BuddyError> {
+        if mem::align_of::<T>() > MAX_ALIGN {
+            return Err(BuddyError::InvalidAlignment);
+        }
+        let len = usize::max(mem::size_of::<T>(), 1);
+        let off = self.alloc_aligned(len, mem::align_of::<T>())?;
+        let end = off + self.usable_size(off);
+        self.memory[off..end].fill(0);
+        Ok(Ptr { off, _marker: PhantomData })
+    }
+    /// Borrows the `T` behind `p`. Panics if `p` is not a live allocation
+    /// big enough to hold a `T`.
+    pub fn deref_obj<T: Plain>(&mut self, p: Ptr<T>) -> &mut T {
+        assert!(self.usable_size(p.off) >= mem::size_of::<T>(), "Dangling pointer");
+        let ptr = self.memory[p.off..].as_mut_ptr() as *mut T;
+        assert_eq!(ptr as usize % mem::align_of::<T>(), 0, "Misaligned pointer");
+        // SAFETY: the block is live, in bounds, aligned and large enough for
+        // a `T`, any bytes form a valid `T: Plain`, and `&mut self` keeps the
+        // borrow unique.
+        unsafe { &mut *ptr }
+    }
+    pub fn free_obj<T: Plain>(&mut self, p: Ptr<T>) -> Result<(), BuddyError> {
+        self.free(p.off, usize::max(mem::size_of::<T>(), 1))
+    }
+    /// Allocates a zeroed array of `count` values of `T` at its natural
+    /// alignment and borrows it as a slice. Fails with `SizeOverflow` if its
+    /// size does not fit in a `usize`. Panics, like `deref_obj`, if the
+    /// memory does not start aligned enough for a `T`.
+    pub fn alloc_slice<T: Plain>(&mut self, count: usize) -> Result<&mut [T], BuddyError> {
+        if mem::align_of::<T>() > MAX_ALIGN {
+            return Err(BuddyError::InvalidAlignment);
+        }
+        let len = count.checked_mul(mem::size_of::<T>()).ok_or(BuddyError::SizeOverflow)?;
+        let off = self.alloc_aligned(usize::max(len, 1), mem::align_of::<T>())?;
+        let end = off + self.usable_size(off);
+        self.memory[off..end].fill(0);
+        let ptr = self.memory[off..].as_mut_ptr() as *mut T;
+        assert_eq!(ptr as usize % mem::align_of::<T>(), 0, "Misaligned pointer");
+        // SAFETY: the block is live, in bounds, aligned and holds `count`
+        // values of `T`, zeroes form valid `T: Plain`, and the borrow of
+        // `self` keeps the slice unique.
+        Ok(unsafe { slice::from_raw_parts_mut(ptr, count) })
+    }
+    /// Frees a slice from `alloc_slice`, finding its offset from its address
+    /// and its length from its own. It takes a raw pointer because the
+    /// slice borrows the allocator: `let p: *const [T] = slice;` ends that
+    /// borrow. Fails with `InvalidPointer` if `slice` does not point into the
+    /// memory.
+    pub fn free_slice<T: Plain>(&mut self, slice: *const [T]) -> Result<(), BuddyError> {
+        let addr = slice as *const T as usize;
+        let off = addr.wrapping_sub(self.memory.base_ptr());
+        if addr < self.memory.base_ptr() || off >= self.size {
+            return Err(BuddyError::InvalidPointer { offset: off });
+        }
+        self.free(off, usize::max(slice.len() * mem::size_of::<T>(), 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BuddyAllocator, BuddyError};
+
+    #[test]
+    fn array_round_trips_through_ptr() {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        let before = heap.available();
+        let p = heap.alloc_obj::<[u64; 8]>().unwrap();
+        assert_eq!(heap.size_of(p.offset()), 64);
+        assert_eq!(*heap.deref_obj(p), [0; 8]);
+        *heap.deref_obj(p) = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(*heap.deref_obj(p), [1, 2, 3, 4, 5, 6, 7, 8]);
+        heap.free_obj(p).unwrap();
+        assert_eq!(heap.available(), before);
+    }
+
+
+    #[test]
+    fn u32_slice_round_trip() {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        let before = heap.available();
+        let slice = heap.alloc_slice::<u32>(100).unwrap();
+        assert_eq!(slice.len(), 100);
+        for (i, v) in slice.iter_mut().enumerate() {
+            *v = i as u32;
+        }
+        assert!(slice.iter().enumerate().all(|(i, &v)| v == i as u32));
+        let p: *const [u32] = slice;
+        heap.free_slice(p).unwrap();
+        assert_eq!(heap.available(), before);
+        assert_eq!(heap.alloc_slice::<u32>(usize::MAX).map(|s| s.len()), Err(BuddyError::SizeOverflow));
+    }
+}
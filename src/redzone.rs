@@ -0,0 +1,72 @@
+use crate::{pptr, BuddyAllocator, BuddyError};
+
+/// Byte written after the requested length of a block while red zones are
+/// on.
+pub const REDZONE: u8 = 0xFD;
+/// Bytes every allocation reserves past its requested length while red
+/// zones are on, so even a request that fills its order has a red zone.
+pub const REDZONE_LEN: usize = 8;
+
+impl BuddyAllocator {
+    /// Turns overrun detection on or off. While on, every allocation keeps
+    /// at least `REDZONE_LEN` bytes past the requested length, filled with
+    /// `REDZONE`, and freeing it checks that they are untouched. Blocks
+    /// allocated while it was off are not checked.
+    pub fn set_redzone(&mut self, on: bool) {
+        self.redzone = on;
+    }
+    /// Bytes of the allocation at `off` the caller may use: the requested
+    /// length when it has a red zone, the whole block otherwise.
+    pub(crate) fn usable_size(&self, off: pptr) -> usize {
+        match self.requested.get(&off) {
+            Some(&len) => len,
+            None => self.size_of(off)
+        }
+    }
+    /// Records `len` as the requested length of the fresh block at `off`
+    /// and fills the rest of the block with `REDZONE`.
+    pub(crate) fn seal(&mut self, off: pptr, len: usize) {
+        self.set_length(off, Some(len));
+        if !self.redzone {
+            self.set_requested(off, None);
+            return;
+        }
+        let end = off + self.size_of(off);
+        self.memory[off + len..end].fill(REDZONE);
+        self.set_requested(off, Some(len));
+    }
+    /// Makes sure nothing was written past the requested length of the
+    /// block of order `idx` at `off`.
+    pub(crate) fn check_redzone(&self, off: pptr, idx: usize) -> Result<(), BuddyError> {
+        let len = match self.requested.get(&off) {
+            Some(&len) => len,
+            None => return Ok(())
+        };
+        match self.memory[off + len..off + (1 << idx)].iter().position(|&b| b != REDZONE) {
+            Some(pos) => Err(BuddyError::BufferOverflow { offset: off + len + pos }),
+            None => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::REDZONE;
+    use crate::{BuddyAllocator, BuddyError};
+
+    #[test]
+    fn write_past_the_requested_length_is_caught() {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        heap.set_redzone(true);
+        let a = heap.alloc(20).unwrap();
+        heap.bytes_mut(a, 20).fill(1);
+        assert_eq!(heap.memory[a + 20], REDZONE);
+        heap.memory[a + 20] = 1;
+        assert_eq!(heap.free(a, 20), Err(BuddyError::BufferOverflow { offset: a + 20 }));
+        assert_eq!(heap.available(), 1024);
+        let b = heap.alloc(20).unwrap();
+        heap.bytes_mut(b, 20).fill(1);
+        assert_eq!(heap.free(b, 20), Ok(()));
+    }
+}
@@ -0,0 +1,146 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::memory::Memory;
+use crate::{num_bits, pptr, Backing, BuddyAllocator, BuddyError, MAX_TAG_LEN};
+
+/// The logical state of an allocator, without the bytes of its memory: the
+/// accounting, every free-list in order and the live allocations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapSnapshot {
+    pub size: usize,
+    pub available: usize,
+    pub last: usize,
+    pub min_order: usize,
+    /// Offsets of the free blocks of each order, from the head of the list.
+    /// There is one list per order the allocator was made with.
+    pub free_lists: Vec<Vec<pptr>>,
+    /// Live allocations as `(offset, order)`, sorted by offset.
+    pub allocated: Vec<(pptr, usize)>,
+    /// Tags of the live allocations that have one, sorted by offset.
+    pub tags: Vec<(pptr, String)>
+}
+
+impl BuddyAllocator {
+    pub fn snapshot(&self) -> HeapSnapshot {
+        let mut free_lists = vec![vec![]; self.buddies.len()];
+        for (idx, off, _) in self.free_blocks() {
+            free_lists[idx].push(off);
+        }
+        for (idx, off) in self.binned() {
+            free_lists[idx].push(off);
+        }
+        HeapSnapshot {
+            size: self.size,
+            available: self.available,
+            last: self.last,
+            min_order: self.min_order,
+            free_lists,
+            allocated: self.report_leaks(),
+            tags: self.tags.iter().map(|(&off, tag)| (off, tag.clone())).collect()
+        }
+    }
+    /// Rebuilds an allocator in the state `snapshot` describes, with a
+    /// zeroed memory of its size. Returns `None` if the snapshot does not
+    /// describe a consistent state, as checked by `validate`.
+    pub fn restore(snapshot: &HeapSnapshot) -> Option<BuddyAllocator> {
+        let mut heap = Self::rebuild(snapshot)?;
+        heap.memory = Box::new(Memory::new(heap.size));
+        Some(heap)
+    }
+    /// Copies the memory into `dst` and returns an allocator that manages
+    /// the copy, with the same free-lists, allocations and settings. Offsets
+    /// count from the start of the memory, so they are valid in both. An
+    /// open transaction is not carried over: the copy holds its changes as
+    /// if it was committed. Fails with `OutOfMemory` if `dst` is smaller
+    /// than the memory.
+    pub fn clone_into<B: Backing + 'static>(&self, mut dst: B) -> Result<BuddyAllocator, BuddyError> {
+        if dst.len() < self.size {
+            return Err(BuddyError::OutOfMemory);
+        }
+        dst[..self.size].copy_from_slice(&self.memory[..self.size]);
+        let mut heap = Self::rebuild(&self.snapshot()).expect("Heap is corrupted");
+        heap.memory = Box::new(dst);
+        heap.max_coalesce_order = self.max_coalesce_order;
+        heap.address_ordered = self.address_ordered;
+        heap.lazy_coalesce = self.lazy_coalesce;
+        heap.max_split_distance = self.max_split_distance;
+        heap.reserved = self.reserved;
+        heap.fast_bin_order = self.fast_bin_order;
+        heap.fast_bin_len = self.fast_bin_len;
+        heap.stats = self.stats;
+        heap.policy = self.policy;
+        heap.poison = self.poison;
+        heap.redzone = self.redzone;
+        heap.requested = self.requested.clone();
+        heap.lengths = self.lengths.clone();
+        Ok(heap)
+    }
+    /// Builds the metadata `snapshot` describes over an empty memory.
+    pub(crate) fn rebuild(snapshot: &HeapSnapshot) -> Option<BuddyAllocator> {
+        let orders = snapshot.free_lists.len();
+        if orders == 0 || orders > num_bits::<usize>() as usize || snapshot.min_order >= orders {
+            return None;
+        }
+        let mut heap = BuddyAllocator::with_orders(orders - 1);
+        heap.size = snapshot.size;
+        heap.available = snapshot.available;
+        heap.last = snapshot.last;
+        heap.min_order = snapshot.min_order;
+        for (idx, list) in snapshot.free_lists.iter().enumerate() {
+            for &off in list.iter().rev() {
+                heap.push(idx, off);
+            }
+        }
+        heap.allocated.extend(snapshot.allocated.iter().copied());
+        heap.stats.live = heap.allocated.len();
+        for (off, tag) in &snapshot.tags {
+            if !heap.allocated.contains_key(off) || tag.len() > MAX_TAG_LEN {
+                return None;
+            }
+            heap.tags.insert(*off, tag.clone());
+        }
+        heap.validate().ok()?;
+        Some(heap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heap() -> BuddyAllocator {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        heap.alloc_tagged(100, "a").unwrap();
+        heap.alloc(16).unwrap();
+        heap
+    }
+
+    #[test]
+    fn restore_round_trips() {
+        let heap = heap();
+        let restored = BuddyAllocator::restore(&heap.snapshot()).unwrap();
+        assert_eq!(restored.snapshot(), heap.snapshot());
+    }
+
+    #[test]
+    fn restore_rejects_bad_orders() {
+        let mut snapshot = heap().snapshot();
+        snapshot.allocated = vec![(0, 64)];
+        assert!(BuddyAllocator::restore(&snapshot).is_none());
+        let mut snapshot = heap().snapshot();
+        snapshot.allocated.push((512, 40));
+        assert!(BuddyAllocator::restore(&snapshot).is_none());
+    }
+
+    #[test]
+    fn restore_rejects_bad_last() {
+        for last in [200, 12, 0] {
+            let mut snapshot = heap().snapshot();
+            snapshot.last = last;
+            assert!(BuddyAllocator::restore(&snapshot).is_none(), "last = {}", last);
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io;
+
+use crate::{pptr, BuddyAllocator};
+
+/// Most allocations `waste_report` lists as the worst.
+const WORST_LEN: usize = 10;
+
+/// Running counters kept by the allocator since `init` or `reset_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub allocs: usize,
+    pub frees: usize,
+    pub live: usize,
+    pub peak_used: usize,
+    pub splits: usize,
+    pub merges: usize
+}
+
+/// The free blocks of one order, as listed by `iter_orders`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrderStats {
+    pub order: usize,
+    pub block_size: usize,
+    pub free_count: usize,
+    pub free_bytes: usize
+}
+
+/// How much of the memory the live allocations take beyond what they asked
+/// for, as given by `waste_report`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WasteReport {
+    pub requested_bytes: usize,
+    /// Full size of the blocks holding the allocations.
+    pub allocated_bytes: usize,
+    /// `allocated_bytes - requested_bytes`: the rounding up to a power of
+    /// two, the minimum order and any red zones.
+    pub wasted_bytes: usize,
+    /// The allocations that waste the most, as `(offset, wasted bytes)`,
+    /// most first.
+    pub worst: Vec<(pptr, usize)>
+}
+
+impl BuddyAllocator {
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+    /// Zeroes the counters. `live` and `peak_used` restart from the
+    /// allocations that are currently live.
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats {
+            live: self.allocated.len(),
+            peak_used: self.used_bytes(),
+            ..Stats::default()
+        };
+    }
+    /// Number of free blocks of each order, indexed by order.
+    pub fn histogram(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.buddies.len()];
+        for s in self.iter_orders() {
+            counts[s.order] = s.free_count;
+        }
+        counts
+    }
+    /// The free blocks of every order a block can have, from the minimum
+    /// order up to the largest block that fits. Nothing is allocated on the
+    /// way.
+    pub fn iter_orders(&self) -> impl Iterator<Item = OrderStats> + '_ {
+        (self.min_order..self.last).map(move |order| {
+            let free_count = self.list_len(order);
+            OrderStats { order, block_size: 1 << order, free_count, free_bytes: free_count << order }
+        })
+    }
+    /// Adds up the bytes every live allocation asked for against the blocks
+    /// they take. An allocation restored from a snapshot or an image has no
+    /// recorded length and counts as asking for its whole block.
+    pub fn waste_report(&self) -> WasteReport {
+        let mut report = WasteReport::default();
+        for (&off, &idx) in &self.allocated {
+            let block = 1 << idx;
+            let len = self.lengths.get(&off).copied().unwrap_or(block);
+            report.requested_bytes += len;
+            report.allocated_bytes += block;
+            if block > len {
+                report.worst.push((off, block - len));
+            }
+        }
+        report.wasted_bytes = report.allocated_bytes - report.requested_bytes;
+        report.worst.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        report.worst.truncate(WORST_LEN);
+        report
+    }
+    /// Number of blocks on the free-list of order `idx`, stopping at as many
+    /// as the memory could fit in case the list loops.
+    fn list_len(&self, idx: usize) -> usize {
+        let mut len = 0;
+        let mut curr = self.buddies[idx].clone();
+        while let Some(b) = curr {
+            if len == self.size >> idx {
+                break;
+            }
+            len += 1;
+            curr = b.borrow().next.clone();
+        }
+        len
+    }
+    /// Writes the state and the counters as metrics in the Prometheus text
+    /// format, with the free blocks of each order under an `order` label.
+    #[cfg(feature = "std")]
+    pub fn write_prometheus<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        let gauges = [
+            ("buddy_total_bytes", "Bytes managed by the allocator.", self.capacity() as f64),
+            ("buddy_free_bytes", "Bytes in free blocks.", self.free_bytes() as f64),
+            ("buddy_used_bytes", "Bytes in live allocations.", self.used_bytes() as f64),
+            ("buddy_largest_free_block", "Bytes in the largest free block.", self.largest_free_block() as f64),
+            ("buddy_fragmentation", "Share of the free bytes outside the largest free block.", self.fragmentation()),
+            ("buddy_live_allocations", "Allocations not yet freed.", self.stats.live as f64),
+            ("buddy_peak_used_bytes", "Most bytes in use at once.", self.stats.peak_used as f64)
+        ];
+        for (name, help, value) in &gauges {
+            writeln!(w, "# HELP {} {}", name, help)?;
+            writeln!(w, "# TYPE {} gauge", name)?;
+            writeln!(w, "{} {}", name, value)?;
+        }
+        let counters = [
+            ("buddy_allocs_total", "Successful allocations.", self.stats.allocs),
+            ("buddy_frees_total", "Successful frees.", self.stats.frees),
+            ("buddy_splits_total", "Blocks split in halves.", self.stats.splits),
+            ("buddy_merges_total", "Buddies merged back together.", self.stats.merges)
+        ];
+        for (name, help, value) in &counters {
+            writeln!(w, "# HELP {} {}", name, help)?;
+            writeln!(w, "# TYPE {} counter", name)?;
+            writeln!(w, "{} {}", name, value)?;
+        }
+        writeln!(w, "# HELP buddy_free_blocks Free blocks of each order.")?;
+        writeln!(w, "# TYPE buddy_free_blocks gauge")?;
+        for s in self.iter_orders() {
+            writeln!(w, "buddy_free_blocks{{order=\"{}\"}} {}", s.order, s.free_count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BuddyAllocator, Stats};
+
+    #[test]
+    fn splits_and_merges_are_counted() {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        let a = heap.alloc(64).unwrap();
+        let b = heap.alloc(64).unwrap();
+        assert_eq!(heap.stats(), Stats { allocs: 2, frees: 0, live: 2, peak_used: 128, splits: 4, merges: 0 });
+        heap.free_ptr(a).unwrap();
+        assert_eq!(heap.stats().merges, 0);
+        heap.free_ptr(b).unwrap();
+        assert_eq!(heap.stats(), Stats { allocs: 2, frees: 2, live: 0, peak_used: 128, splits: 4, merges: 4 });
+        heap.alloc(256).unwrap();
+        heap.reset_stats();
+        assert_eq!(heap.stats(), Stats { live: 1, peak_used: 256, ..Stats::default() });
+    }
+
+    #[test]
+    fn histogram_counts_free_blocks_by_order() {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        let blocks: Vec<_> = [32, 32, 64, 128, 256].iter().map(|&len| heap.alloc(len).unwrap()).collect();
+        let mut expected = vec![0; heap.histogram().len()];
+        expected[9] = 1;
+        assert_eq!(heap.histogram(), expected);
+        heap.free_ptr(blocks[0]).unwrap();
+        heap.free_ptr(blocks[3]).unwrap();
+        heap.free_ptr(blocks[4]).unwrap();
+        expected[5] = 1;
+        expected[7] = 1;
+        expected[8] = 1;
+        assert_eq!(heap.histogram(), expected);
+        let free: usize = heap.histogram().iter().enumerate().map(|(idx, n)| n << idx).sum();
+        assert_eq!(free, heap.available());
+    }
+}
@@ -0,0 +1,237 @@
+use std::alloc::{GlobalAlloc, Layout};
+use std::ptr;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::{pptr, BuddyAllocator, BuddyError};
+
+/// A `BuddyAllocator` behind a lock, so it can be shared through an `Arc`.
+pub struct SyncBuddy {
+    inner: Mutex<BuddyAllocator>
+}
+
+// SAFETY: `BuddyAllocator` is only `!Send` because its free-lists are made
+// of `Rc` nodes. Those nodes are never handed out, so every clone of an `Rc`
+// is owned by the same allocator, and the mutex makes sure only one thread
+// touches their reference counts at a time.
+unsafe impl Send for SyncBuddy {}
+unsafe impl Sync for SyncBuddy {}
+
+impl SyncBuddy {
+    pub fn new(allocator: BuddyAllocator) -> Self {
+        SyncBuddy {
+            inner: Mutex::new(allocator)
+        }
+    }
+    fn lock(&self) -> MutexGuard<'_, BuddyAllocator> {
+        self.inner.lock().unwrap()
+    }
+    pub fn alloc(&self, len: usize) -> Result<pptr, BuddyError> {
+        self.lock().alloc(len)
+    }
+    pub fn free(&self, off: pptr, len: usize) -> Result<(), BuddyError> {
+        self.lock().free(off, len)
+    }
+    pub fn available(&self) -> usize {
+        self.lock().available()
+    }
+    pub fn print(&self) {
+        self.lock().print()
+    }
+    /// Tells whether `ptr` points into the memory, which is how a
+    /// `#[global_allocator]` that mixes this with another allocator knows
+    /// whose block it is freeing.
+    pub fn owns_ptr(&self, ptr: *const u8) -> bool {
+        let heap = self.lock();
+        let base = heap.memory.base_ptr();
+        (ptr as usize).checked_sub(base).is_some_and(|off| off < heap.capacity())
+    }
+    /// Like `alloc`, but the block is freed when the returned guard is
+    /// dropped. Unlike `BuddyAllocator::alloc_guarded`, the allocator stays
+    /// shared while the guard lives.
+    pub fn alloc_owned(&self, len: usize) -> Result<SyncAllocation<'_>, BuddyError> {
+        let off = self.alloc(len)?;
+        Ok(SyncAllocation { heap: self, off, len })
+    }
+}
+
+/// An allocation from a `SyncBuddy` that is freed when it goes out of
+/// scope. Forgetting it leaks the block.
+pub struct SyncAllocation<'a> {
+    heap: &'a SyncBuddy,
+    off: pptr,
+    len: usize
+}
+
+impl SyncAllocation<'_> {
+    pub fn offset(&self) -> pptr {
+        self.off
+    }
+    /// Address of the first byte, valid until the guard is dropped, since
+    /// the memory of a `SyncBuddy` cannot move. Accesses through it take no
+    /// lock.
+    pub fn as_ptr(&self) -> *mut u8 {
+        let mut heap = self.heap.lock();
+        // SAFETY: `off` is a live allocation inside the memory.
+        unsafe { heap.memory.as_mut_ptr().add(self.off) }
+    }
+    /// Runs `f` on the `len` bytes that were asked for, with the allocator
+    /// locked.
+    pub fn with_bytes<R, F: FnOnce(&mut [u8]) -> R>(&mut self, f: F) -> R {
+        f(self.heap.lock().bytes_mut(self.off, self.len))
+    }
+}
+
+impl Drop for SyncAllocation<'_> {
+    fn drop(&mut self) {
+        let _ = self.heap.lock().free_ptr(self.off);
+    }
+}
+
+/// Hands out real pointers into the memory, so a `SyncBuddy` can stand in
+/// wherever a `GlobalAlloc` is expected. Sizes and alignments map onto
+/// `alloc_aligned`, and running out of memory gives a null pointer.
+/// A block is aligned to its offset only as far as the start of the
+/// memory is, which is `MAX_ALIGN` after `init`, so a larger alignment
+/// gives null unless the block happens to land on it. Pointers stay valid
+/// as long as the `SyncBuddy` does, since its memory can no longer grow or
+/// move.
+///
+/// It cannot be the `#[global_allocator]` of the program on its own: it
+/// has to be built at run time, as `BuddyAllocator::new` allocates, and
+/// its free-lists and maps are allocated from the global allocator, so each
+/// call would call back into this one while holding its lock. A shim that
+/// sends those inner allocations to `System` can, as
+/// `tests/global_alloc.rs` shows.
+unsafe impl GlobalAlloc for SyncBuddy {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.lock();
+        let off = match heap.alloc_aligned(usize::max(layout.size(), 1), layout.align()) {
+            Ok(off) => off,
+            Err(_) => return ptr::null_mut()
+        };
+        let addr = heap.memory.base_ptr() + off;
+        if !addr.is_multiple_of(layout.align()) {
+            let _ = heap.free_ptr(off);
+            return ptr::null_mut();
+        }
+        heap.memory.as_mut_ptr().add(off)
+    }
+    /// Does nothing for a pointer that does not point into the memory.
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        let mut heap = self.lock();
+        if let Some(off) = (ptr as usize).checked_sub(heap.memory.base_ptr()) {
+            let _ = heap.free_ptr(off);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn heap(size: usize) -> SyncBuddy {
+        let mut heap = BuddyAllocator::new();
+        heap.init(size);
+        SyncBuddy::new(heap)
+    }
+
+    #[test]
+    fn global_alloc_round_trip() {
+        let heap = heap(4096);
+        unsafe {
+            let layout = Layout::from_size_align(100, 64).unwrap();
+            let ptr = GlobalAlloc::alloc(&heap, layout);
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % 64, 0);
+            assert!(heap.owns_ptr(ptr));
+            ptr.write_bytes(5, 100);
+            let ptr = GlobalAlloc::realloc(&heap, ptr, layout, 1000);
+            assert_eq!(*ptr.add(99), 5);
+            GlobalAlloc::dealloc(&heap, ptr, Layout::from_size_align(1000, 64).unwrap());
+        }
+        assert_eq!(heap.available(), 4096);
+    }
+
+    #[test]
+    fn global_alloc_gives_null_when_full() {
+        let heap = heap(1024);
+        unsafe {
+            assert!(GlobalAlloc::alloc(&heap, Layout::from_size_align(2048, 8).unwrap()).is_null());
+        }
+        assert!(!heap.owns_ptr(core::ptr::null()));
+    }
+
+    #[test]
+    fn owned_allocation_frees_on_drop() {
+        let heap = heap(1024);
+        let mut a = heap.alloc_owned(10).unwrap();
+        a.with_bytes(|b| b.fill(7));
+        assert_eq!(unsafe { *a.as_ptr().add(9) }, 7);
+        assert_eq!(heap.available(), 1008);
+        drop(a);
+        assert_eq!(heap.available(), 1024);
+        core::mem::forget(heap.alloc_owned(10).unwrap());
+        assert_eq!(heap.available(), 1008);
+    }
+
+    #[test]
+    fn threads_share_one_heap() {
+        let heap = Arc::new(heap(1 << 16));
+        let start = heap.available();
+        let threads: Vec<_> = (0..8)
+            .map(|t| {
+                let heap = heap.clone();
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        let len = 1 + (t * 37 + i) % 500;
+                        let off = heap.alloc(len).unwrap();
+                        heap.lock().bytes_mut(off, len).fill(t as u8);
+                        assert!(heap.lock().bytes(off, len).iter().all(|&b| b == t as u8));
+                        heap.free(off, len).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(heap.available(), start);
+        assert_eq!(heap.lock().validate(), Ok(()));
+    }
+
+    #[test]
+    fn global_alloc_from_several_threads() {
+        let heap = Arc::new(heap(1 << 16));
+        let start = heap.available();
+        let threads: Vec<_> = (0..8)
+            .map(|t| {
+                let heap = heap.clone();
+                thread::spawn(move || {
+                    let layout = Layout::from_size_align(8 << t, 8).unwrap();
+                    for _ in 0..2000 {
+                        unsafe {
+                            let ptr = GlobalAlloc::alloc(&*heap, layout);
+                            assert!(!ptr.is_null());
+                            ptr.write_bytes(t as u8, layout.size());
+                            assert_eq!(*ptr.add(layout.size() - 1), t as u8);
+                            GlobalAlloc::dealloc(&*heap, ptr, layout);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        unsafe {
+            let foreign = Box::into_raw(Box::new(0u8));
+            GlobalAlloc::dealloc(&*heap, ptr::dangling_mut(), Layout::new::<u8>());
+            GlobalAlloc::dealloc(&*heap, foreign, Layout::new::<u8>());
+            drop(Box::from_raw(foreign));
+        }
+        assert_eq!(heap.available(), start);
+        assert_eq!(heap.lock().validate(), Ok(()));
+    }
+}
@@ -0,0 +1,25 @@
+use alloc::string::String;
+
+use crate::{pptr, BuddyAllocator, BuddyError};
+
+/// Longest tag, in bytes, that `alloc_tagged` accepts.
+pub const MAX_TAG_LEN: usize = 64;
+
+impl BuddyAllocator {
+    /// Like `alloc`, but labels the allocation with `tag`, which `tag_of`
+    /// and `walk_allocated` give back until it is freed. The tag moves with
+    /// the data on `realloc` and `compact`, and is saved with the metadata.
+    /// Fails with `TagTooLong` if `tag` is longer than `MAX_TAG_LEN` bytes.
+    pub fn alloc_tagged(&mut self, len: usize, tag: &str) -> Result<pptr, BuddyError> {
+        if tag.len() > MAX_TAG_LEN {
+            return Err(BuddyError::TagTooLong);
+        }
+        let off = self.alloc(len)?;
+        self.set_tag(off, Some(String::from(tag)));
+        Ok(off)
+    }
+    /// The tag given to the live allocation at `off`, if it has one.
+    pub fn tag_of(&self, off: pptr) -> Option<&str> {
+        self.tags.get(&off).map(String::as_str)
+    }
+}
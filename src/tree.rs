@@ -0,0 +1,127 @@
+#[cfg(feature = "std")]
+use std::io;
+
+use crate::{pptr, BuddyAllocator};
+
+/// How `dump_tree` lays out the tree of blocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TreeFormat {
+    /// One line per block, indented by how many times its ancestors were
+    /// split.
+    #[default]
+    Text,
+    /// A Graphviz digraph with an edge from every split block to its halves.
+    Dot
+}
+
+/// What became of a block in the tree.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Node {
+    Free,
+    /// Free, but held in a fast bin rather than on its free-list.
+    Cached,
+    Allocated,
+    Split,
+    /// Neither free, allocated nor splittable, which `validate` rejects.
+    Missing
+}
+
+impl Node {
+    fn name(self) -> &'static str {
+        match self {
+            Node::Free => "free",
+            Node::Cached => "cached",
+            Node::Allocated => "allocated",
+            Node::Split => "split",
+            Node::Missing => "missing"
+        }
+    }
+    fn color(self) -> &'static str {
+        match self {
+            Node::Free => "palegreen",
+            Node::Cached => "khaki",
+            Node::Allocated => "lightcoral",
+            Node::Split => "white",
+            Node::Missing => "gray"
+        }
+    }
+}
+
+impl BuddyAllocator {
+    /// Writes the tree of blocks the memory is split into, from the blocks
+    /// of a fresh memory down to every free or allocated block, in `format`.
+    /// Where a block sits in the tree comes from the free-lists and the live
+    /// allocations, so the tree is only as right as they are.
+    #[cfg(feature = "std")]
+    pub fn dump_tree<W: io::Write>(&self, w: &mut W, format: TreeFormat) -> io::Result<()> {
+        if format == TreeFormat::Dot {
+            writeln!(w, "digraph buddy {{")?;
+            writeln!(w, "    node [shape=box style=filled];")?;
+        }
+        for (idx, off) in self.aligned_blocks(0, self.size) {
+            self.dump_node(w, format, off, idx, 0)?;
+        }
+        if format == TreeFormat::Dot {
+            writeln!(w, "}}")?;
+        }
+        Ok(())
+    }
+    #[cfg(feature = "std")]
+    fn dump_node<W: io::Write>(&self, w: &mut W, format: TreeFormat, off: pptr, idx: usize, depth: usize) -> io::Result<()> {
+        let node = self.node(off, idx);
+        match format {
+            TreeFormat::Text => writeln!(
+                w, "{:indent$}{}..{} [{}] {}", "", off, off + (1 << idx) - 1, idx, node.name(), indent = 2 * depth
+            )?,
+            TreeFormat::Dot => writeln!(
+                w, "    n{}_{} [label=\"{}\\n{} bytes\" fillcolor={}];", off, idx, off, 1 << idx, node.color()
+            )?
+        }
+        if node == Node::Split {
+            for child in [off, off + (1 << (idx - 1))] {
+                if format == TreeFormat::Dot {
+                    writeln!(w, "    n{}_{} -> n{}_{};", off, idx, child, idx - 1)?;
+                }
+                self.dump_node(w, format, child, idx - 1, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+    fn node(&self, off: pptr, idx: usize) -> Node {
+        if matches!(self.free.get(&off), Some(&(i, _)) if i == idx) {
+            Node::Free
+        } else if self.fast_bins[idx].contains(&off) {
+            Node::Cached
+        } else if self.allocated.get(&off) == Some(&idx) {
+            Node::Allocated
+        } else if idx > self.min_order {
+            Node::Split
+        } else {
+            Node::Missing
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::{BuddyAllocator, TreeFormat};
+
+    #[test]
+    fn one_split_makes_three_nodes() {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1024);
+        heap.alloc(512).unwrap();
+        let mut out = vec![];
+        heap.dump_tree(&mut out, TreeFormat::Dot).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+        assert!(dot.starts_with("digraph buddy {"));
+        assert_eq!(dot.lines().filter(|l| l.contains("[label=")).count(), 3);
+        assert_eq!(dot.lines().filter(|l| l.contains(" -> ")).count(), 2);
+        assert!(dot.contains("n0_9 [label=\"0\\n512 bytes\" fillcolor=lightcoral]"));
+        assert!(dot.contains("n512_9 [label=\"512\\n512 bytes\" fillcolor=palegreen]"));
+
+        let mut out = vec![];
+        heap.dump_tree(&mut out, TreeFormat::Text).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "0..1023 [10] split\n  0..511 [9] allocated\n  512..1023 [9] free\n");
+    }
+}
@@ -0,0 +1,383 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{pptr, BuddyAllocator, BuddyError};
+
+const ALLOC: u64 = 1;
+const FREE: u64 = 2;
+const COMMIT: u64 = 3;
+
+/// One entry of the log, written as four little-endian words: the kind and
+/// up to three arguments. An allocation carries the order of its block as
+/// well as its offset, so it replays to the same block whatever the
+/// allocator settings. A commit marker carries the number of records it
+/// seals.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Record {
+    Alloc { len: usize, off: pptr, order: usize },
+    Free { off: pptr },
+    Commit { count: usize }
+}
+
+impl Record {
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let words = match *self {
+            Record::Alloc { len, off, order } => [ALLOC, len as u64, off as u64, order as u64],
+            Record::Free { off } => [FREE, off as u64, 0, 0],
+            Record::Commit { count } => [COMMIT, count as u64, 0, 0]
+        };
+        for word in &words {
+            w.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+    /// Reads the next record, or `None` at the end of the log or at a record
+    /// that was only partly written.
+    fn read<R: Read>(r: &mut R) -> Option<Record> {
+        let mut words = [0; 4];
+        for word in words.iter_mut() {
+            let mut buf = [0; 8];
+            r.read_exact(&mut buf).ok()?;
+            *word = u64::from_le_bytes(buf);
+        }
+        match words {
+            [ALLOC, len, off, order] => Some(Record::Alloc { len: len as usize, off: off as pptr, order: order as usize }),
+            [FREE, off, 0, 0] => Some(Record::Free { off: off as pptr }),
+            [COMMIT, count, 0, 0] => Some(Record::Commit { count: count as usize }),
+            _ => None
+        }
+    }
+}
+
+/// Keeps a `BuddyAllocator` crash-consistent on disk with a checkpoint
+/// image and a write-ahead log next to it.
+///
+/// `alloc` and `free` take effect in memory and join the open transaction,
+/// which `commit` appends to the log followed by a commit marker and syncs.
+/// Once `commit` returns, the changes survive a crash. `checkpoint` folds
+/// the log into a new image and empties it.
+///
+/// On `open`, `recover` replays every committed transaction in the log over
+/// the image and drops a trailing one that lacks its marker, so the heap
+/// comes back as of the last commit. Only the allocator metadata is logged:
+/// bytes written into the memory are saved by `checkpoint` alone.
+///
+/// Both files start with a generation number. A checkpoint bumps it, so a
+/// log whose changes the image already holds is recognized and ignored.
+pub struct WalBackend {
+    path: PathBuf,
+    log: File,
+    heap: BuddyAllocator,
+    generation: u64,
+    pending: Option<Vec<Record>>
+}
+
+impl WalBackend {
+    /// Opens the image at `path` and its log at `path` with a `.wal`
+    /// extension, and recovers the committed state. If the image is missing
+    /// or empty, a fresh one of `size` bytes is started. Fails with
+    /// `InvalidData`, leaving the image as it is, if it holds anything but a
+    /// valid allocator.
+    pub fn open<P: AsRef<Path>>(path: P, size: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let fresh = match fs::metadata(&path) {
+            Ok(meta) => meta.len() == 0,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => true,
+            Err(e) => return Err(e)
+        };
+        let log = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path.with_extension("wal"))?;
+        let mut wal = WalBackend { path, log, heap: BuddyAllocator::new(), generation: 0, pending: None };
+        if fresh {
+            wal.heap.init(size);
+            wal.checkpoint()?;
+        }
+        wal.recover()?;
+        Ok(wal)
+    }
+    /// Reads the image back into the heap, or returns `None` if it is
+    /// missing or not valid.
+    fn load_image(&mut self) -> Option<()> {
+        let mut r = BufReader::new(File::open(&self.path).ok()?);
+        let mut buf = [0; 8];
+        r.read_exact(&mut buf).ok()?;
+        let mut heap = BuddyAllocator::load_metadata(&mut r)?;
+        r.read_exact(&mut heap.memory).ok()?;
+        self.generation = u64::from_le_bytes(buf);
+        self.heap = heap;
+        Some(())
+    }
+    /// Brings the heap back to the last commit: reloads the image, replays
+    /// the committed transactions in the log over it and cuts off whatever
+    /// follows the last commit marker. The open transaction, if any, is
+    /// lost. Returns how many transactions were replayed.
+    ///
+    /// Fails with `InvalidData` if the image cannot be read or a committed
+    /// record does not apply to it, which means the log does not belong to
+    /// the image. Allocations are put back at their logged block rather
+    /// than allocated again, so no setting has to match the run that wrote
+    /// the log.
+    pub fn recover(&mut self) -> io::Result<usize> {
+        self.pending = None;
+        if self.load_image().is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Image is not valid"));
+        }
+        self.log.seek(SeekFrom::Start(0))?;
+        let mut r = BufReader::new(&self.log);
+        let mut buf = [0; 8];
+        if r.read_exact(&mut buf).is_err() || u64::from_le_bytes(buf) != self.generation {
+            drop(r);
+            return self.reset_log().map(|_| 0);
+        }
+        let mut end = 8;
+        let mut txs = 0;
+        let mut records = vec![];
+        while let Some(record) = Record::read(&mut r) {
+            match record {
+                Record::Commit { count } if count == records.len() => {
+                    for record in records.drain(..) {
+                        Self::replay(&mut self.heap, record)?;
+                    }
+                    end = r.stream_position()?;
+                    txs += 1;
+                }
+                Record::Commit { .. } => break,
+                record => records.push(record)
+            }
+        }
+        drop(r);
+        self.log.set_len(end)?;
+        self.log.seek(SeekFrom::End(0))?;
+        self.log.sync_data()?;
+        Ok(txs)
+    }
+    fn replay(heap: &mut BuddyAllocator, record: Record) -> io::Result<()> {
+        let ok = match record {
+            Record::Alloc { len, off, order } => heap.take_block(off, order, len).is_ok(),
+            Record::Free { off } => matches!(heap.free_ptr(off), Ok(()) | Err(BuddyError::BufferOverflow { .. })),
+            Record::Commit { .. } => true
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "Log does not match the image"))
+        }
+    }
+    pub fn heap(&self) -> &BuddyAllocator {
+        &self.heap
+    }
+    /// Gives access to the heap for reading and writing data. Allocating or
+    /// freeing through it bypasses the log.
+    pub fn heap_mut(&mut self) -> &mut BuddyAllocator {
+        &mut self.heap
+    }
+    /// Starts a transaction if none is open.
+    fn begin(&mut self) {
+        if self.pending.is_none() {
+            self.heap.tx_begin();
+            self.pending = Some(vec![]);
+        }
+    }
+    fn record(&mut self, record: Record) {
+        if let Some(records) = &mut self.pending {
+            records.push(record);
+        }
+    }
+    pub fn alloc(&mut self, len: usize) -> Result<pptr, BuddyError> {
+        self.begin();
+        let off = self.heap.alloc(len)?;
+        let order = self.heap.allocated[&off];
+        self.record(Record::Alloc { len, off, order });
+        Ok(off)
+    }
+    /// Frees the allocation at `off`. A `BufferOverflow` from the red zone
+    /// check still frees the block, so it is logged like any other free.
+    pub fn free(&mut self, off: pptr) -> Result<(), BuddyError> {
+        self.begin();
+        let res = self.heap.free_ptr(off);
+        if matches!(res, Ok(()) | Err(BuddyError::BufferOverflow { .. })) {
+            self.record(Record::Free { off });
+        }
+        res
+    }
+    /// Makes the open transaction durable. Does nothing if there is none.
+    /// If writing the log fails, the log is cut back to where it was and the
+    /// transaction stays open, so `commit` can be retried.
+    pub fn commit(&mut self) -> io::Result<()> {
+        let records = match &self.pending {
+            Some(records) => records,
+            None => return Ok(())
+        };
+        let start = self.log.stream_position()?;
+        if let Err(e) = Self::append(&self.log, records) {
+            self.log.set_len(start)?;
+            self.log.seek(SeekFrom::Start(start))?;
+            return Err(e);
+        }
+        self.pending = None;
+        self.heap.tx_end();
+        Ok(())
+    }
+    fn append(log: &File, records: &[Record]) -> io::Result<()> {
+        let mut w = BufWriter::new(log);
+        for record in records {
+            record.write(&mut w)?;
+        }
+        Record::Commit { count: records.len() }.write(&mut w)?;
+        w.flush()?;
+        drop(w);
+        log.sync_data()
+    }
+    /// Rolls the heap back to the last commit, dropping the open
+    /// transaction.
+    pub fn abort(&mut self) {
+        if self.pending.take().is_some() {
+            self.heap.tx_abort();
+        }
+    }
+    /// Commits the open transaction, writes the heap as a new image and
+    /// empties the log. The image is written to a temporary file and renamed
+    /// over the old one, so a crash leaves either the old image and log or
+    /// the new ones.
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        self.commit()?;
+        let tmp = self.path.with_extension("tmp");
+        self.write_image(&tmp, self.generation + 1)?;
+        fs::rename(&tmp, &self.path)?;
+        self.generation += 1;
+        self.reset_log()
+    }
+    fn write_image(&self, path: &Path, generation: u64) -> io::Result<()> {
+        let file = File::create(path)?;
+        let mut w = BufWriter::new(&file);
+        w.write_all(&generation.to_le_bytes())?;
+        self.heap.save_metadata(&mut w)?;
+        w.write_all(&self.heap.memory)?;
+        w.flush()?;
+        drop(w);
+        file.sync_data()
+    }
+    fn reset_log(&mut self) -> io::Result<()> {
+        self.log.set_len(0)?;
+        self.log.seek(SeekFrom::Start(0))?;
+        self.log.write_all(&self.generation.to_le_bytes())?;
+        self.log.sync_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Policy;
+
+    /// A path in the temporary directory that no other test uses.
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("rust-buddy-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("wal"));
+        path
+    }
+
+    #[test]
+    fn reopen_with_non_default_policy() {
+        let path = temp_path("wal-policy");
+        let live = {
+            let mut wal = WalBackend::open(&path, 1024).unwrap();
+            wal.heap_mut().set_policy(Policy::TopDown);
+            let a = wal.alloc(16).unwrap();
+            wal.alloc(64).unwrap();
+            wal.free(a).unwrap();
+            wal.alloc(16).unwrap();
+            wal.commit().unwrap();
+            wal.heap().report_leaks()
+        };
+        let wal = WalBackend::open(&path, 1024).unwrap();
+        assert_eq!(wal.heap().report_leaks(), live);
+        assert_eq!(wal.heap().validate(), Ok(()));
+        let _ = fs::remove_file(path.with_extension("wal"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn uncommitted_changes_are_dropped() {
+        let path = temp_path("wal-uncommitted");
+        {
+            let mut wal = WalBackend::open(&path, 1024).unwrap();
+            wal.alloc(100).unwrap();
+            wal.commit().unwrap();
+            wal.alloc(100).unwrap();
+        }
+        let wal = WalBackend::open(&path, 1024).unwrap();
+        assert_eq!(wal.heap().report_leaks().len(), 1);
+        let _ = fs::remove_file(path.with_extension("wal"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn corrupt_image_is_not_overwritten() {
+        let path = temp_path("wal-corrupt");
+        {
+            let mut wal = WalBackend::open(&path, 1024).unwrap();
+            wal.alloc(100).unwrap();
+            wal.checkpoint().unwrap();
+        }
+        let mut image = fs::read(&path).unwrap();
+        image[20] ^= 1;
+        fs::write(&path, &image).unwrap();
+        let err = WalBackend::open(&path, 1024).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read(&path).unwrap(), image);
+        let _ = fs::remove_file(path.with_extension("wal"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn overrun_block_is_still_logged_as_freed() {
+        let path = temp_path("wal-overrun");
+        {
+            let mut wal = WalBackend::open(&path, 1024).unwrap();
+            wal.heap_mut().set_redzone(true);
+            let a = wal.alloc(16).unwrap();
+            wal.heap_mut().memory[a + 16] = 0;
+            assert_eq!(wal.free(a), Err(BuddyError::BufferOverflow { offset: a + 16 }));
+            wal.commit().unwrap();
+        }
+        let wal = WalBackend::open(&path, 1024).unwrap();
+        assert!(wal.heap().report_leaks().is_empty());
+        assert_eq!(wal.heap().available(), 1024);
+        let _ = fs::remove_file(path.with_extension("wal"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn recovery_stops_at_the_last_whole_commit() {
+        let path = temp_path("wal-torn");
+        let first = {
+            let mut wal = WalBackend::open(&path, 1024).unwrap();
+            wal.alloc(100).unwrap();
+            wal.commit().unwrap();
+            let first = wal.heap().report_leaks();
+            wal.alloc(200).unwrap();
+            wal.free(first[0].0).unwrap();
+            wal.commit().unwrap();
+            first
+        };
+        // The generation, then one alloc and its marker, then the second
+        // transaction cut off halfway through its free record.
+        let log = path.with_extension("wal");
+        let kept = 8 + 2 * 32;
+        File::options().write(true).open(&log).unwrap().set_len(kept + 32 + 12).unwrap();
+        let mut wal = WalBackend::open(&path, 1024).unwrap();
+        assert_eq!(wal.heap().report_leaks(), first);
+        assert_eq!(wal.heap().validate(), Ok(()));
+        assert_eq!(fs::metadata(&log).unwrap().len(), kept);
+        assert_eq!(wal.recover().unwrap(), 1);
+        let _ = fs::remove_file(&log);
+        let _ = fs::remove_file(&path);
+    }
+}
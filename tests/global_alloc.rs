@@ -0,0 +1,79 @@
+//! Runs a program whose global allocator is a `SyncBuddy`, through a shim
+//! that sends the allocator's own bookkeeping, and everything allocated
+//! before it is set up, to `System`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::OnceLock;
+
+use rust_buddy::{BuddyAllocator, SyncBuddy};
+
+static HEAP: OnceLock<SyncBuddy> = OnceLock::new();
+
+thread_local! {
+    /// Set while this thread is inside the buddy allocator, whose own
+    /// allocations must not come back to it.
+    static INSIDE: Cell<bool> = const { Cell::new(false) };
+}
+
+struct Shim;
+
+impl Shim {
+    /// Runs `f` on the buddy allocator, unless it is not set up yet or this
+    /// thread is already in it.
+    fn with_heap<R>(f: impl FnOnce(&SyncBuddy) -> R) -> Option<R> {
+        let heap = HEAP.get()?;
+        if INSIDE.try_with(|inside| inside.replace(true)).unwrap_or(true) {
+            return None;
+        }
+        let res = f(heap);
+        INSIDE.with(|inside| inside.set(false));
+        Some(res)
+    }
+}
+
+unsafe impl GlobalAlloc for Shim {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match Self::with_heap(|heap| GlobalAlloc::alloc(heap, layout)) {
+            Some(ptr) if !ptr.is_null() => ptr,
+            _ => System.alloc(layout)
+        }
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let freed = Self::with_heap(|heap| {
+            let owned = heap.owns_ptr(ptr);
+            if owned {
+                GlobalAlloc::dealloc(heap, ptr, layout);
+            }
+            owned
+        });
+        if freed != Some(true) {
+            System.dealloc(ptr, layout);
+        }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Shim = Shim;
+
+#[test]
+fn vec_pushes_go_through_the_buddy_allocator() {
+    HEAP.get_or_init(|| {
+        let mut heap = BuddyAllocator::new();
+        heap.init(1 << 20);
+        SyncBuddy::new(heap)
+    });
+    let heap = HEAP.get().unwrap();
+    let before = heap.available();
+    let mut v: Vec<u64> = Vec::new();
+    for i in 0..1000 {
+        v.push(i);
+    }
+    assert!(heap.owns_ptr(v.as_ptr() as *const u8));
+    assert!(heap.available() < before);
+    assert_eq!(v.iter().sum::<u64>(), 999 * 1000 / 2);
+    let boxed = Box::new([7u8; 100]);
+    assert!(heap.owns_ptr(boxed.as_ptr()));
+    drop(boxed);
+    drop(v);
+}